@@ -0,0 +1,89 @@
+use crate::vec3::{Vec3, Float};
+use crate::color::{Color, new_color};
+use crate::raytracer::BLACK;
+use crate::tonemap::{tone_map, DEFAULT_TONEMAP};
+
+pub const AOV_NAMES : [&str ; 7] = ["depth", "normal", "albedo", "shadow",
+                                    "reflection", "object", "material"];
+
+// Arbitrary output values of the first hit through a pixel. The ids are
+// the indices of the object and of its material plus one, 0 being the
+// background.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Aov {
+    pub depth: Float,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub shadow: Float,
+    pub reflection: Color,
+    pub object: u32,
+    pub material: u32
+}
+
+pub const NO_HIT : Aov = Aov {
+    depth: Float::INFINITY,
+    normal: BLACK,
+    albedo: BLACK,
+    shadow: 0.0,
+    reflection: BLACK,
+    object: 0,
+    material: 0
+};
+
+// Raw values of a pass, for the floating point formats.
+pub fn aov_pass(aovs: &[Aov], name: &str) -> Option<Vec<Color>> {
+    let gray = |v: Float| new_color(v, v, v);
+    let pass = match name {
+        "depth" => aovs.iter().map(|a| gray(a.depth)).collect(),
+        "normal" => aovs.iter().map(|a| a.normal).collect(),
+        "albedo" => aovs.iter().map(|a| a.albedo).collect(),
+        "shadow" => aovs.iter().map(|a| gray(a.shadow)).collect(),
+        "reflection" => aovs.iter().map(|a| a.reflection).collect(),
+        "object" => aovs.iter().map(|a| gray(a.object as Float)).collect(),
+        "material" =>
+            aovs.iter().map(|a| gray(a.material as Float)).collect(),
+        _ => return None
+    };
+    Some(pass)
+}
+
+// Values of a pass in 0..1, ready to be stored in 8 bits images: depth
+// from white (near) to black (far), normals remapped from -1..1, colors
+// gamma encoded and ids turned into arbitrary colors.
+pub fn aov_display(aovs: &[Aov], name: &str) -> Option<Vec<Color>> {
+    let pass = aov_pass(aovs, name)?;
+    let display = match name {
+        "depth" => {
+            let far = aovs.iter().map(|a| a.depth)
+                .filter(|d| d.is_finite())
+                .fold(0.0, Float::max);
+            pass.iter()
+                .map(|d| if d.0.is_finite() && far > 0.0 {
+                    let v = 1.0 - d.0 / far;
+                    new_color(v, v, v)
+                } else {
+                    BLACK
+                })
+                .collect()
+        },
+        "normal" => pass.iter()
+            .map(|n| (*n + new_color(1.0, 1.0, 1.0)) * 0.5)
+            .collect(),
+        "albedo" | "reflection" => tone_map(&pass, &DEFAULT_TONEMAP),
+        "object" | "material" => pass.iter()
+            .map(|id| id_color(id.0 as u32))
+            .collect(),
+        _ => pass
+    };
+    Some(display)
+}
+
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return BLACK;
+    }
+    let h = id.wrapping_mul(0x9e37_79b9);
+    new_color((h >> 24) as Float / 255.0,
+              (h >> 16 & 0xff) as Float / 255.0,
+              (h >> 8 & 0xff) as Float / 255.0)
+}
@@ -0,0 +1,164 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::fs;
+use std::fs::File;
+use crate::vec3::Float;
+use crate::film::{Film, read_film};
+use crate::raytracer::Scene;
+use crate::writer::write_scene;
+
+const MAGIC : &[u8] = b"RTCKPT1\n";
+
+// Where and how often to save the render state, `hash` identifies the
+// scene being rendered.
+#[derive(Debug,Clone,PartialEq)]
+pub struct CheckpointConfig {
+    pub filename: String,
+    pub interval: Float,
+    pub hash: u64
+}
+
+// Render state: the passes already done, the tiles of the current pass
+// already merged into the film and the samples per pixel taken so far.
+pub struct Checkpoint {
+    pub hash: u64,
+    pub pass: u32,
+    pub tiles: u32,
+    pub samples: u32,
+    pub film: Film
+}
+
+impl Checkpoint {
+    // Whether the state was saved for the scene of hash `hash`, rendered
+    // at the same size.
+    pub fn fits(&self, hash: u64, width: u32, height: u32) -> bool {
+        self.hash == hash && self.film.width == width &&
+            self.film.height == height
+    }
+}
+
+// FNV-1a of the scene as loaded, written in the canonical form with the
+// includes expanded, and of the settings that change the samples but are
+// not written: the seed, the crop rectangle and the frame number. The
+// tile range is left out, the partials of a render sharing the hash.
+pub fn hash_scene(scene: &Scene, frame: i64) -> u64 {
+    let mut text = Vec::new();
+    write_scene(scene, &mut text).unwrap_or_default();
+    let crop = scene.crop.map_or([0 ; 4], |c| [c.x0, c.y0, c.x1, c.y1]);
+    let hash = fnv(0xcbf2_9ce4_8422_2325, &text);
+    let hash = fnv(hash, &scene.seed.to_le_bytes());
+    let hash = crop.iter().fold(hash, |h, v| fnv(h, &v.to_le_bytes()));
+    fnv(hash, &frame.to_le_bytes())
+}
+
+// Mixes the range of tiles rendered into the hash of the scene, a
+// checkpoint only resuming the same tiles.
+pub fn hash_tile_range(hash: u64, range: Option<(usize, usize)>) -> u64 {
+    let (start, end) = range.map_or((0, usize::MAX), |r| r);
+    let hash = fnv(hash, &(start as u64).to_le_bytes());
+    fnv(hash, &(end as u64).to_le_bytes())
+}
+
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |h, b| {
+        (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub fn save_checkpoint(filename: &str, state: &Checkpoint) -> io::Result<()> {
+    // written aside first so that a crash never leaves a truncated file
+    let tmp = format!("{}.tmp", filename);
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&state.hash.to_le_bytes())?;
+    for v in [state.pass, state.tiles, state.samples] {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    state.film.write(&mut out)?;
+    out.flush()?;
+    drop(out);
+    fs::rename(tmp, filename)
+}
+
+pub fn load_checkpoint(filename: &str) -> io::Result<Checkpoint> {
+    let mut input = BufReader::new(File::open(filename)?);
+    let mut magic = [0_u8 ; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  "not a checkpoint file"));
+    }
+    let hash = read_u64(&mut input)?;
+    let pass = read_u32(&mut input)?;
+    let tiles = read_u32(&mut input)?;
+    let samples = read_u32(&mut input)?;
+    let film = read_film(&mut input)?;
+    Ok(Checkpoint { hash, pass, tiles, samples, film })
+}
+
+pub fn read_u32(input: &mut dyn Read) -> io::Result<u32> {
+    let mut buf = [0_u8 ; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut buf = [0_u8 ; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::DEFAULT_FILTER;
+    use crate::film::new_film;
+    use crate::color::new_color;
+    use crate::scene::load_scene;
+    use crate::testutil::temp_file;
+
+    fn film_bytes(film: &Film) -> Vec<u8> {
+        let mut out = Vec::new();
+        film.write(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn saved_state_loads_back() {
+        let mut film = new_film(0, 0, 4, 3);
+        film.add_sample(&DEFAULT_FILTER, (1, 2), (0.5, 0.5),
+                        new_color(0.25, 0.5, 1.0));
+        let state = Checkpoint { hash: 42, pass: 3, tiles: 7, samples: 12,
+                                 film };
+        let file = temp_file("ckpt");
+        save_checkpoint(file.name(), &state).unwrap();
+        let loaded = load_checkpoint(file.name()).unwrap();
+        assert_eq!((loaded.hash, loaded.pass, loaded.tiles, loaded.samples),
+                   (42, 3, 7, 12));
+        assert_eq!(film_bytes(&loaded.film), film_bytes(&state.film));
+        assert!(loaded.fits(42, 4, 3));
+    }
+
+    #[test]
+    fn other_scene_is_rejected() {
+        let conf = concat!(env!("CARGO_MANIFEST_DIR"), "/test.conf");
+        let mut scene = load_scene(conf, 0).unwrap();
+        let hash = hash_scene(&scene, 0);
+        assert_ne!(hash_scene(&scene, 1), hash);
+        assert_ne!(hash_tile_range(hash, Some((0, 4))), hash);
+        scene.seed = 1;
+        assert_ne!(hash_scene(&scene, 0), hash);
+        let state = Checkpoint { hash, pass: 0, tiles: 0, samples: 0,
+                                 film: new_film(0, 0, 4, 3) };
+        assert!(!state.fits(hash + 1, 4, 3));
+        assert!(!state.fits(hash, 4, 4));
+    }
+
+    #[test]
+    fn other_file_is_rejected() {
+        let file = temp_file("ckpt");
+        fs::write(&file.path, "P3\n1 1\n255\n0 0 0\n").unwrap();
+        assert!(load_checkpoint(file.name()).is_err());
+    }
+}
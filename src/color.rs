@@ -1,5 +1,7 @@
 use std::f32::consts::PI;
 use crate::vec3::{Float, Vec3, Dot};
+use crate::tonemap::{srgb_to_linear, encode_srgb};
+use crate::expr::{Expr, new_form, new_symbol, new_float};
 
 pub type Color = Vec3;
 
@@ -7,6 +9,37 @@ pub fn new_color(r: Float, g: Float, b: Float) -> Color {
     Vec3(r, g, b)
 }
 
+// Colors are authored as sRGB values in the 0..255 range, and rendered
+// in linear light with 1.0 for white.
+pub fn from_srgb(c: Color) -> Color {
+    new_color(srgb_to_linear(c.0 / 255.0),
+              srgb_to_linear(c.1 / 255.0),
+              srgb_to_linear(c.2 / 255.0))
+}
+
+// The inverse of `from_srgb`, rounded to the third decimal.
+pub fn to_srgb(c: Color) -> Color {
+    let encode = |x: Float| (encode_srgb(x) * 255_000.0).round() / 1000.0;
+    new_color(encode(c.0), encode(c.1), encode(c.2))
+}
+
+fn color_expr(c: &Color) -> Expr {
+    let c = to_srgb(*c);
+    new_form("color", vec![new_float(c.0), new_float(c.1), new_float(c.2)])
+}
+
+fn specular_expr((shininess, strength): (Float, Float)) -> Expr {
+    new_form("spec", vec![new_float(shininess), new_float(strength)])
+}
+
+fn reflection_expr(reflection: Float) -> Expr {
+    new_form("reflection", vec![new_float(reflection)])
+}
+
+pub fn luminance(c: &Color) -> Float {
+    0.2126 * c.0 + 0.7152 * c.1 + 0.0722 * c.2
+}
+
 #[derive(Debug,Clone,Copy)]
 pub struct Lights {
     pub dir: Vec3,
@@ -16,14 +49,17 @@ pub struct Lights {
 
 pub trait Material : Sync + Send {
     fn get_color(&self, p : &Vec3, n : &Vec3, lights : &Lights) -> Color;
+    // Base color of the surface, without any lighting.
+    fn get_albedo(&self, p : &Vec3) -> Color;
     fn get_reflection(&self) -> Float;
+    // The shader and its arguments, as written after the material name.
+    fn to_expr(&self) -> Vec<Expr>;
     fn get_specular(&self, specular: (Float, Float),
                     n: &Vec3, light: Vec3) -> Float {
         specular.1 *
             ((*n * -1.0).dot(&light).acos() /
              (2.0 * PI * 1.0/3.0)).powf(specular.0)
     }
-    fn clone_box(&self) -> Box<dyn Material>;
 }
 
 #[derive(Clone)]
@@ -38,11 +74,17 @@ impl Material for Solid {
         let spec = self.get_specular(self.specular, n, lights.dir);
         self.color * (spec + lights.ambiant)
     }    
+    fn get_albedo(&self, _p : &Vec3) -> Color {
+        self.color
+    }
     fn get_reflection(&self) -> Float {
         self.reflection
     }
-    fn clone_box(&self) -> Box<dyn Material> {
-        Box::new(self.clone())
+    fn to_expr(&self) -> Vec<Expr> {
+        vec![new_symbol("solid"),
+             color_expr(&self.color),
+             specular_expr(self.specular),
+             reflection_expr(self.reflection)]
     }
 }
 
@@ -57,6 +99,10 @@ pub struct Checker {
 impl Material for Checker {
     fn get_color(&self, p : &Vec3, n : &Vec3, lights : &Lights) -> Color {
         let spec = self.get_specular(self.specular, n, lights.dir);
+        self.get_albedo(p) * (self.specular.1 * spec + lights.ambiant)
+    }
+
+    fn get_albedo(&self, p : &Vec3) -> Color {
         // I'm pretty sure there's a bug in here...
         let check =
             (p.0 as i32 % self.uv * 2 <
@@ -65,19 +111,23 @@ impl Material for Checker {
              if p.1 > 0.0 { self.uv } else { -self.uv }) ^
             (p.2 as i32 % self.uv * 2 <
              if p.2 > 0.0 { self.uv } else { -self.uv });
-        let color = if check {
+        if check {
             self.colors.0
         } else {
             self.colors.1
-        };
-        color * (self.specular.1 * spec + lights.ambiant)
+        }
     }
     
     fn get_reflection(&self) -> Float {
         self.reflection
     }
 
-    fn clone_box(&self) -> Box<dyn Material>{
-        Box::new(self.clone())
+    fn to_expr(&self) -> Vec<Expr> {
+        vec![new_symbol("checkboard"),
+             color_expr(&self.colors.0),
+             color_expr(&self.colors.1),
+             new_form("uv", vec![Expr::Num(i64::from(self.uv))]),
+             specular_expr(self.specular),
+             reflection_expr(self.reflection)]
     }
 }
@@ -0,0 +1,119 @@
+use crate::vec3::{Float, Dot};
+use crate::color::{Color, luminance};
+use crate::raytracer::BLACK;
+use crate::aov::Aov;
+
+// Post process smoothing the noise of low sample renders, guided by the
+// AOVs so that edges, creases and texture boundaries are kept.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum DenoiseKind {
+    Bilateral,
+    NonLocalMeans
+}
+
+// `radius` is the half width of the search window, in pixels.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Denoise {
+    pub kind: DenoiseKind,
+    pub radius: u32
+}
+
+pub const DEFAULT_DENOISE_RADIUS : u32 = 5;
+
+// Standard deviations of the weights.
+const SIGMA_COLOR : Float = 0.5;
+const SIGMA_ALBEDO : Float = 0.1;
+const SIGMA_NORMAL : Float = 0.1;
+const SIGMA_DEPTH : Float = 0.05;
+const PATCH : i64 = 1;
+
+pub fn get_denoise_kind(name: &str) -> Option<DenoiseKind> {
+    match name {
+        "bilateral" => Some(DenoiseKind::Bilateral),
+        "nlm" => Some(DenoiseKind::NonLocalMeans),
+        _ => None
+    }
+}
+
+pub fn denoise_kind_name(kind: DenoiseKind) -> &'static str {
+    match kind {
+        DenoiseKind::Bilateral => "bilateral",
+        DenoiseKind::NonLocalMeans => "nlm"
+    }
+}
+
+// `aovs` must cover the same pixels as the frame.
+pub fn denoise(frame: &[Color], aovs: &[Aov], width: u32, height: u32,
+               settings: &Denoise) -> Vec<Color> {
+    let (w, h) = (i64::from(width), i64::from(height));
+    let r = i64::from(settings.radius);
+    let sigma_space = (r as Float / 2.0).max(0.5);
+    (0..h).flat_map(|y| (0..w).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let i = (y * w + x) as usize;
+            let mut sum = BLACK;
+            let mut total = 0.0;
+            for ny in (y - r).max(0)..(y + r + 1).min(h) {
+                for nx in (x - r).max(0)..(x + r + 1).min(w) {
+                    let j = (ny * w + nx) as usize;
+                    let d2 = ((nx - x).pow(2) + (ny - y).pow(2)) as Float;
+                    let range = match settings.kind {
+                        DenoiseKind::Bilateral =>
+                            color_distance(&frame[i], &frame[j]),
+                        DenoiseKind::NonLocalMeans =>
+                            patch_distance(frame, (w, h), (x, y), (nx, ny))
+                    };
+                    let weight = (-d2 / (2.0 * sigma_space.powi(2))
+                                  - range / (2.0 * SIGMA_COLOR.powi(2)))
+                        .exp() * guide_weight(&aovs[i], &aovs[j]);
+                    sum = sum + frame[j] * weight;
+                    total += weight;
+                }
+            }
+            if total > 0.0 { sum * (1.0 / total) } else { frame[i] }
+        })
+        .collect()
+}
+
+// Squared difference relative to the brightness, so that the highlights
+// are not smoothed less than the shadows.
+fn color_distance(a: &Color, b: &Color) -> Float {
+    let d = *a - *b;
+    d.dot(&d) / (0.01 + luminance(a) + luminance(b)).powi(2)
+}
+
+// Mean color distance of the patches around two pixels.
+fn patch_distance(frame: &[Color], (w, h): (i64, i64),
+                  (x0, y0): (i64, i64), (x1, y1): (i64, i64)) -> Float {
+    let mut sum = 0.0;
+    let mut n = 0;
+    for dy in -PATCH..=PATCH {
+        for dx in -PATCH..=PATCH {
+            let (ax, ay, bx, by) = (x0 + dx, y0 + dy, x1 + dx, y1 + dy);
+            if ax < 0 || ay < 0 || bx < 0 || by < 0 ||
+                ax >= w || ay >= h || bx >= w || by >= h {
+                continue;
+            }
+            sum += color_distance(&frame[(ay * w + ax) as usize],
+                                  &frame[(by * w + bx) as usize]);
+            n += 1;
+        }
+    }
+    if n > 0 { sum / n as Float } else { 0.0 }
+}
+
+// Likeness of the surfaces seen through two pixels.
+fn guide_weight(a: &Aov, b: &Aov) -> Float {
+    if a.depth.is_finite() != b.depth.is_finite() {
+        return 0.0;
+    }
+    if !a.depth.is_finite() {
+        return 1.0;
+    }
+    let da = a.albedo - b.albedo;
+    let dn = 1.0 - a.normal.dot(&b.normal);
+    let dz = (a.depth - b.depth) / a.depth.max(Float::EPSILON);
+    (-da.dot(&da) / (2.0 * SIGMA_ALBEDO.powi(2))
+     - dn * dn / (2.0 * SIGMA_NORMAL.powi(2))
+     - dz * dz / (2.0 * SIGMA_DEPTH.powi(2))).exp()
+}
@@ -0,0 +1,583 @@
+use std::fs;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::expr::{Expr, car, cdr, list_to_vec, vec_to_list, parse_all,
+                  tokenize, new_symbol};
+use crate::scene::FORMS;
+
+// Values bound by `define`, `let` and the loops, plus `frame` and `pi`.
+// `random` draws from `rng`, which `(random-seed n)` reseeds: the same
+// seed gives the same scene on every frame. `files` is the stack of the
+// files being expanded, the innermost last. `names` are the materials and
+// groups defined so far, which the variables do not replace.
+pub struct Env {
+    vars: HashMap<String, Expr>,
+    rng: StdRng,
+    files: Vec<PathBuf>,
+    names: Vec<String>
+}
+
+pub fn new_env(frame: i64) -> Env {
+    let mut vars = HashMap::new();
+    vars.insert("frame".to_string(), Expr::Num(frame));
+    vars.insert("pi".to_string(), Expr::Float(std::f64::consts::PI));
+    Env { vars, rng: StdRng::seed_from_u64(0), files: Vec::new(),
+          names: Vec::new() }
+}
+
+// Reads, parses and expands a scene file.
+pub fn expand_file(path: &Path, env: &mut Env, out: &mut Vec<Expr>)
+                   -> Option<()> {
+    let canonical = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    if env.files.contains(&canonical) {
+        let cycle : Vec<String> = env.files.iter().chain([&canonical])
+            .map(|f| f.display().to_string())
+            .collect();
+        println!("include cycle: {}", cycle.join(" -> "));
+        return None;
+    }
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            println!("unable to read {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    let exprs = tokenize(contents.as_str())
+        .and_then(|tokens| parse_all(&tokens));
+    let Some(exprs) = exprs else {
+        println!("Parse error in {}", path.display());
+        return None;
+    };
+    env.files.push(canonical);
+    let res = expand(&exprs, env, out);
+    env.files.pop();
+    res
+}
+
+// (include "file" [namespace]) expands a file given relative to the
+// including one. With a namespace the materials, groups and variables it
+// defines are renamed `namespace.name`, and the previous bindings of the
+// variables are kept.
+fn include(args: &Expr, env: &mut Env, out: &mut Vec<Expr>) -> Option<()> {
+    // a string, or a symbol for the paths without spaces
+    let file = match eval(&car(args), env)? {
+        Expr::Str(file) | Expr::Symbol(file) => file,
+        _ => return invalid(args)
+    };
+    let dir = env.files.last()
+        .and_then(|f| f.parent())
+        .map_or(PathBuf::new(), Path::to_path_buf);
+    let path = dir.join(file);
+    let ns = match car(&cdr(args)) {
+        Expr::Nil => return expand_file(&path, env, out),
+        Expr::Symbol(ns) => ns,
+        _ => return invalid(args)
+    };
+    let before = env.vars.clone();
+    let mut forms = Vec::new();
+    expand_file(&path, env, &mut forms)?;
+    let changed : Vec<(String, Expr)> = env.vars.iter()
+        .filter(|(var, value)| before.get(*var) != Some(*value))
+        .map(|(var, value)| (var.clone(), value.clone()))
+        .collect();
+    for (var, value) in changed {
+        restore(env, &var, before.get(&var).cloned());
+        env.vars.insert(format!("{}.{}", ns, var), value);
+    }
+    let names : Vec<String> = forms.iter()
+        .filter(|f| matches!(car(f), Expr::Symbol(s) if s == "mat" ||
+                             s == "group"))
+        .filter_map(|f| match car(&cdr(f)) {
+            Expr::Symbol(name) => Some(name),
+            _ => None
+        })
+        .collect();
+    out.extend(forms.iter().map(|f| rename(f, &names, &ns)));
+    env.names.extend(names.iter().map(|name| format!("{}.{}", ns, name)));
+    Some(())
+}
+
+fn invalid<T>(form: &Expr) -> Option<T> {
+    println!("invalid form: {}", form);
+    None
+}
+
+// Prefixes the names in `names` where the form defines or refers to them:
+// the name of a material or a group, the material of an object, given by
+// position or after `:material`, the group of an instance and the objects
+// of a group. Other symbols, such as shaders or tags, are left as is.
+fn rename(form: &Expr, names: &[String], ns: &str) -> Expr {
+    let mut args = list_to_vec(&cdr(form));
+    let head = match car(form) {
+        Expr::Symbol(head) => head,
+        _ => String::new()
+    };
+    let i = match head.as_str() {
+        "mat" | "instance" => Some(0),
+        "group" => {
+            for object in args.iter_mut().skip(1) {
+                *object = rename(object, names, ns);
+            }
+            Some(0)
+        },
+        // the material comes after the geometry
+        "sphere" | "plane" => Some(args.iter()
+                                   .position(|e| *e == new_symbol(":material"))
+                                   .map_or(2, |i| i + 1)),
+        _ => None
+    };
+    if let Some(Expr::Symbol(name)) = i.and_then(|i| args.get_mut(i)) {
+        if names.contains(name) {
+            *name = format!("{}.{}", ns, name);
+        }
+    }
+    Expr::Cons(Box::new(car(form)), Box::new(vec_to_list(args)))
+}
+
+// Expands the top level forms: `define`, `random-seed`, `include`, `let`,
+// `repeat` and `for-each` are run, the bodies of groups are expanded and
+// the other forms are pushed to `out` with their arguments evaluated.
+fn expand(forms: &[Expr], env: &mut Env, out: &mut Vec<Expr>)
+              -> Option<()> {
+    for form in forms {
+        let Expr::Cons(head, args) = form else {
+            out.push(form.clone());
+            continue;
+        };
+        let name = match head.as_ref() {
+            Expr::Symbol(s) => s.as_str(),
+            _ => ""
+        };
+        match name {
+            "define" => {
+                let Expr::Symbol(var) = car(args) else {
+                    return invalid(form)
+                };
+                let value = eval(&car(&cdr(args)), env)?;
+                env.vars.insert(var, value);
+            },
+            "random-seed" => {
+                let Expr::Num(seed) = eval(&car(args), env)? else {
+                    return invalid(form)
+                };
+                env.rng = StdRng::seed_from_u64(seed as u64);
+            },
+            "include" => include(args, env, out)?,
+            "let" => {
+                let body = list_to_vec(&cdr(args));
+                with_bindings(&car(args), env,
+                              |env| expand(&body, env, out))?;
+            },
+            "group" => {
+                // (group name form...), the forms are expanded in place
+                let mut body = Vec::new();
+                expand(&list_to_vec(&cdr(args)), env, &mut body)?;
+                if let Expr::Symbol(name) = car(args) {
+                    env.names.push(name);
+                }
+                out.push(vec_to_list([vec![(**head).clone(), car(args)],
+                                      body].concat()));
+            },
+            "repeat" => {
+                // (repeat var from to form...), `to` excluded
+                let Expr::Symbol(var) = car(args) else {
+                    return invalid(form)
+                };
+                let Expr::Num(from) = eval(&car(&cdr(args)), env)? else {
+                    return invalid(form)
+                };
+                let Expr::Num(to) = eval(&car(&cdr(&cdr(args))), env)?
+                else {
+                    return invalid(form)
+                };
+                let body = list_to_vec(&cdr(&cdr(&cdr(args))));
+                let values = (from..to).map(Expr::Num).collect();
+                expand_loop(&var, values, &body, env, out)?;
+            },
+            "for-each" => {
+                // (for-each var (value...) form...)
+                let Expr::Symbol(var) = car(args) else {
+                    return invalid(form)
+                };
+                let values = list_to_vec(&eval(&car(&cdr(args)), env)?);
+                let body = list_to_vec(&cdr(&cdr(args)));
+                expand_loop(&var, values, &body, env, out)?;
+            },
+            _ => {
+                // the arguments of the form are evaluated, not its name,
+                // nor the name and shader of a material
+                let named = if name == "mat" { 2 } else { 0 };
+                let values = list_to_vec(args).iter().enumerate()
+                    .map(|(i, e)| match e {
+                        Expr::Symbol(_) if i < named => Some(e.clone()),
+                        e => eval(e, env)
+                    })
+                    .collect::<Option<Vec<Expr>>>()?;
+                if let (true, Some(Expr::Symbol(mat))) = (named > 0,
+                                                          values.first()) {
+                    env.names.push(mat.clone());
+                }
+                out.push(Expr::Cons(head.clone(),
+                                    Box::new(vec_to_list(values))));
+            }
+        }
+    }
+    Some(())
+}
+
+fn expand_loop(var: &str, values: Vec<Expr>, body: &[Expr], env: &mut Env,
+               out: &mut Vec<Expr>) -> Option<()> {
+    let saved = env.vars.get(var).cloned();
+    let res = values.into_iter().try_for_each(|value| {
+        env.vars.insert(var.to_string(), value);
+        expand(body, env, out)
+    });
+    restore(env, var, saved);
+    res
+}
+
+fn restore(env: &mut Env, var: &str, saved: Option<Expr>) {
+    match saved {
+        Some(value) => env.vars.insert(var.to_string(), value),
+        None => env.vars.remove(var)
+    };
+}
+
+// Runs `f` with the `((name value) ...)` bindings, the values being
+// evaluated in order so that later ones may use the earlier ones.
+fn with_bindings<T>(bindings: &Expr, env: &mut Env,
+                    f: impl FnOnce(&mut Env) -> Option<T>) -> Option<T> {
+    let mut saved = Vec::new();
+    let mut ok = true;
+    for binding in list_to_vec(bindings) {
+        let Expr::Symbol(var) = car(&binding) else {
+            println!("invalid binding: {}", binding);
+            ok = false;
+            break;
+        };
+        let Some(value) = eval(&car(&cdr(&binding)), env) else {
+            ok = false;
+            break;
+        };
+        let value = (var, value);
+        saved.push((value.0.clone(), env.vars.get(&value.0).cloned()));
+        env.vars.insert(value.0, value.1);
+    }
+    let res = if ok { f(env) } else { None };
+    for (var, value) in saved.into_iter().rev() {
+        restore(env, &var, value);
+    }
+    res
+}
+
+fn to_float(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Num(n) => Some(*n as f64),
+        Expr::Float(f) => Some(*f),
+        _ => None
+    }
+}
+
+// Evaluates the operator calls and replaces the bound symbols in the
+// expression. Anything else is data for the scene loader and is left as
+// is, only the elements of lists being evaluated: `((* 2 r) 0.0 1.0)`
+// becomes a vector, as does `(x 0.0 1.0)` with `x` bound. The names of
+// the forms, such as `color` in `(color r g b)`, are not replaced at the
+// head of a list. The failing expressions are printed.
+pub fn eval(expr: &Expr, env: &mut Env) -> Option<Expr> {
+    match expr {
+        // the names of the materials and groups win over the variables
+        Expr::Symbol(s) if env.names.contains(s) => Some(expr.clone()),
+        Expr::Symbol(s) => Some(env.vars.get(s).cloned()
+                                .unwrap_or_else(|| expr.clone())),
+        Expr::Cons(head, args) => {
+            if let Expr::Symbol(op) = head.as_ref() {
+                match op.as_str() {
+                    "quote" => return Some(car(args)),
+                    "keys" => return eval_keys(args, env),
+                    "random" => return eval_random(args, env),
+                    "let" => {
+                        // the value of the last expression of the body
+                        let body = list_to_vec(&cdr(args));
+                        return with_bindings(&car(args), env, |env| {
+                            body.iter()
+                                .try_fold(Expr::Nil, |_, e| eval(e, env))
+                        });
+                    },
+                    _ => {}
+                }
+                if let Some(value) = apply(op, args, env) {
+                    return value;
+                }
+            }
+            let head = match head.as_ref() {
+                Expr::Symbol(s) if FORMS.contains(&s.as_str()) =>
+                    head.as_ref().clone(),
+                e => eval(e, env)?
+            };
+            let values = list_to_vec(args).iter()
+                .map(|e| eval(e, env))
+                .collect::<Option<Vec<Expr>>>()?;
+            Some(Expr::Cons(Box::new(head), Box::new(vec_to_list(values))))
+        },
+        _ => Some(expr.clone())
+    }
+}
+
+// Calls the builtin `op`, None when there is no such builtin and Some(None)
+// when the call fails, which is reported.
+fn apply(op: &str, args: &Expr, env: &mut Env) -> Option<Option<Expr>> {
+    if arith_op(op).is_none() && float_op(op).is_none() &&
+        !matches!(op, "/" | "min" | "max" | "pow") {
+        return None;
+    }
+    let Some(values) = list_to_vec(args).iter()
+        .map(|e| eval(e, env))
+        .collect::<Option<Vec<Expr>>>() else {
+        // the failing argument is already reported
+        return Some(None);
+    };
+    Some(call(op, &values).or_else(|| report(op, args)))
+}
+
+fn report<T>(op: &str, args: &Expr) -> Option<T> {
+    println!("cannot evaluate {}",
+             Expr::Cons(Box::new(Expr::Symbol(op.to_string())),
+                        Box::new(args.clone())));
+    None
+}
+
+fn arith_op(op: &str) -> Option<fn(i64, i64) -> Option<i64>> {
+    match op {
+        "+" => Some(i64::checked_add),
+        "-" => Some(i64::checked_sub),
+        "*" => Some(i64::checked_mul),
+        _ => None
+    }
+}
+
+fn float_op(op: &str) -> Option<fn(f64) -> f64> {
+    match op {
+        "sin" => Some(f64::sin),
+        "cos" => Some(f64::cos),
+        "tan" => Some(f64::tan),
+        "sqrt" => Some(f64::sqrt),
+        "abs" => Some(f64::abs),
+        "floor" => Some(f64::floor),
+        "ceil" => Some(f64::ceil),
+        "exp" => Some(f64::exp),
+        "log" => Some(f64::ln),
+        _ => None
+    }
+}
+
+fn call(op: &str, values: &[Expr]) -> Option<Expr> {
+    if let Some(f) = arith_op(op) {
+        return arithmetic(op, f, values);
+    }
+    if let Some(f) = float_op(op) {
+        let [x] = values else { return None };
+        return Some(Expr::Float(f(to_float(x)?)));
+    }
+    let xs = values.iter().map(to_float)
+        .collect::<Option<Vec<f64>>>()?;
+    let (first, rest) = xs.split_first()?;
+    match op {
+        // no division by zero, integer or not
+        "/" if rest.contains(&0.0) || (rest.is_empty() && *first == 0.0) =>
+            None,
+        "/" => Some(divide(values).unwrap_or_else(|| match rest {
+            [] => Expr::Float(1.0 / first),
+            _ => Expr::Float(rest.iter().fold(*first, |a, b| a / b))
+        })),
+        "pow" => match rest {
+            [e] => Some(Expr::Float(first.powf(*e))),
+            _ => None
+        },
+        _ => {
+            // min and max keep integers as such
+            let pick = values.iter().zip(xs.iter())
+                .reduce(|a, b| match (op, b.1 < a.1) {
+                    ("min", true) | ("max", false) => b,
+                    _ => a
+                })?;
+            Some(pick.0.clone())
+        }
+    }
+}
+
+// Integer division, when all the operands are integers dividing evenly.
+// A single operand is inverted.
+fn divide(values: &[Expr]) -> Option<Expr> {
+    let ints = values.iter()
+        .map(|v| match v { Expr::Num(n) => Some(*n), _ => None })
+        .collect::<Option<Vec<i64>>>()?;
+    let (first, rest) = match ints.as_slice() {
+        [n] => (1, std::slice::from_ref(n)),
+        [first, rest @ ..] => (*first, rest),
+        [] => return None
+    };
+    rest.iter()
+        .try_fold(first, |a, b| match a.checked_rem(*b) {
+            Some(0) => a.checked_div(*b),
+            _ => None
+        })
+        .map(Expr::Num)
+}
+
+// Integer arithmetic as long as all the operands are integers and the
+// result fits. A single operand is negated by `-`.
+fn arithmetic(op: &str, f: fn(i64, i64) -> Option<i64>, values: &[Expr])
+              -> Option<Expr> {
+    let (first, rest) = values.split_first()?;
+    if op == "-" && rest.is_empty() {
+        return arithmetic(op, f, &[Expr::Num(0), first.clone()]);
+    }
+    let ints = values.iter()
+        .map(|v| match v { Expr::Num(n) => Some(*n), _ => None })
+        .collect::<Option<Vec<i64>>>();
+    // on overflow the operation is done again in floating point
+    let r = ints.and_then(|ints| {
+        let (first, rest) = ints.split_first()?;
+        rest.iter().try_fold(*first, |a, b| f(a, *b))
+    });
+    if let Some(r) = r {
+        return Some(Expr::Num(r));
+    }
+    let floats = values.iter().map(to_float)
+        .collect::<Option<Vec<f64>>>()?;
+    let (first, rest) = floats.split_first()?;
+    let r = rest.iter().fold(*first, |a, b| match op {
+        "+" => a + b,
+        "-" => a - b,
+        _ => a * b
+    });
+    Some(Expr::Float(r))
+}
+
+// (random lo hi) draws a number in [lo, hi), an integer when both bounds
+// are integers.
+fn eval_random(args: &Expr, env: &mut Env) -> Option<Expr> {
+    let lo = eval(&car(args), env)?;
+    let hi = eval(&car(&cdr(args)), env)?;
+    match (lo, hi) {
+        (Expr::Num(lo), Expr::Num(hi)) if lo < hi =>
+            Some(Expr::Num(env.rng.gen_range(lo, hi))),
+        (lo, hi) => {
+            let (Some(lo), Some(hi)) = (to_float(&lo), to_float(&hi)) else {
+                return report("random", args);
+            };
+            Some(Expr::Float(lo + (hi - lo) * env.rng.gen::<f64>()))
+        }
+    }
+}
+
+fn lerp_expr(a: &Expr, b: &Expr, t: f64) -> Option<Expr> {
+    match (a, b) {
+        (Expr::Cons(_, _), Expr::Cons(_, _)) => {
+            let (xs, ys) = (list_to_vec(a), list_to_vec(b));
+            if xs.len() != ys.len() {
+                println!("keyframes of different lengths: {} and {}", a, b);
+                return None;
+            }
+            let values = xs.iter().zip(ys.iter())
+                .map(|(x, y)| lerp_expr(x, y, t))
+                .collect::<Option<Vec<Expr>>>()?;
+            Some(vec_to_list(values))
+        },
+        _ => {
+            let x = to_float(a)?;
+            let y = to_float(b)?;
+            Some(Expr::Float(x + (y - x) * t))
+        }
+    }
+}
+
+// (keys (frame value) ...) evaluates to the value at the current frame,
+// linearly interpolated between the surrounding keyframes and held
+// constant before the first and after the last one.
+fn eval_keys(expr: &Expr, env: &mut Env) -> Option<Expr> {
+    let frame = to_float(env.vars.get("frame")?)?;
+    let keys = list_to_vec(expr).iter()
+        .map(|k| Some((eval(&car(k), env)?, eval(&car(&cdr(k)), env)?)))
+        .collect::<Option<Vec<(Expr, Expr)>>>()?;
+    let Some(keys) = keys.into_iter()
+        .map(|(f, v)| Some((to_float(&f)?, v)))
+        .collect::<Option<Vec<(f64, Expr)>>>()
+        .filter(|keys| !keys.is_empty()) else {
+        return report("keys", expr);
+    };
+    let (first, last) = (&keys[0], &keys[keys.len() - 1]);
+    if frame <= first.0 {
+        return Some(first.1.clone());
+    }
+    for w in keys.windows(2) {
+        let ((f0, v0), (f1, v1)) = (&w[0], &w[1]);
+        if frame < *f1 {
+            return lerp_expr(v0, v1, (frame - f0) / (f1 - f0))
+                .or_else(|| report("keys", expr));
+        }
+    }
+    Some(last.1.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_nums(op: &str, values: &[i64]) -> Option<Expr> {
+        let values : Vec<Expr> = values.iter().map(|n| Expr::Num(*n))
+            .collect();
+        call(op, &values)
+    }
+
+    #[test]
+    fn integers_stay_integers() {
+        assert_eq!(call_nums("+", &[1, 2, 3]), Some(Expr::Num(6)));
+        assert_eq!(call_nums("-", &[5]), Some(Expr::Num(-5)));
+        assert_eq!(call_nums("/", &[12, 2, 3]), Some(Expr::Num(2)));
+        assert_eq!(call_nums("/", &[7, 2]), Some(Expr::Float(3.5)));
+        assert_eq!(call_nums("/", &[4]), Some(Expr::Float(0.25)));
+        assert_eq!(call("+", &[Expr::Num(1), Expr::Float(0.5)]),
+                   Some(Expr::Float(1.5)));
+    }
+
+    #[test]
+    fn overflow_falls_back_to_float() {
+        let max = i64::MAX;
+        assert_eq!(call_nums("+", &[max, 1]),
+                   Some(Expr::Float(max as f64 + 1.0)));
+        assert_eq!(call_nums("*", &[max, 2]),
+                   Some(Expr::Float(max as f64 * 2.0)));
+        assert_eq!(call_nums("-", &[i64::MIN]),
+                   Some(Expr::Float(-(i64::MIN as f64))));
+        assert_eq!(call_nums("/", &[i64::MIN, -1]),
+                   Some(Expr::Float(-(i64::MIN as f64))));
+    }
+
+    #[test]
+    fn division_by_zero_fails() {
+        assert_eq!(call_nums("/", &[1, 0]), None);
+        assert_eq!(call_nums("/", &[0]), None);
+        assert_eq!(call("/", &[Expr::Float(1.0), Expr::Float(0.0)]), None);
+        let mut env = new_env(0);
+        let e = parse_all(&tokenize("(+ 1 (/ 2 0))").unwrap()).unwrap();
+        assert_eq!(eval(&e[0], &mut env), None);
+    }
+
+    #[test]
+    fn bound_heads_are_replaced_but_not_forms() {
+        let mut env = new_env(0);
+        env.vars.insert("x".to_string(), Expr::Num(2));
+        env.vars.insert("color".to_string(), Expr::Num(3));
+        let forms = parse_all(&tokenize("(x 0 (* x 15)) (color x 0 0)")
+                              .unwrap()).unwrap();
+        let values = forms.iter().map(|e| eval(e, &mut env).unwrap())
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(values, ["(2 0 30)", "(color 2 0 0)"]);
+    }
+}
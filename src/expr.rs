@@ -1,10 +1,20 @@
 use std::fmt;
+use crate::vec3::{Vec3, Float};
+
+// Longest line written by `pretty`.
+const WIDTH : usize = 79;
+
+// Forms ending with a body, and their number of arguments before it.
+const BODY_FORMS : [(&str, usize) ; 4] = [("group", 1), ("let", 1),
+                                          ("repeat", 3), ("for-each", 2)];
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Lpar,
     Rpar,
     Dot,
+    Quote,
+    Str(String),
     Word(String)
 }
 
@@ -14,6 +24,7 @@ pub enum Expr {
     Symbol(String),
     Num(i64),
     Float(f64),
+    Str(String),
     Cons(Box<Expr>, Box<Expr>),
 }
 
@@ -23,15 +34,41 @@ impl fmt::Display for Expr {
             Expr::Nil => write!(f, "()"),
             Expr::Symbol(s) => write!(f, "{}", s),
             Expr::Num(i) => write!(f, "{}", i),
-            Expr::Float(i) => write!(f, "{}", i),
-            Expr::Cons(ref c1, ref c2) => {
-                write!(f, "({}", *c1)?;
-                fmt_cons(c2, f)                
+            // keeps the point so that it reads back as a float
+            Expr::Float(x) if x.fract() == 0.0 && x.abs() < 1e15 =>
+                write!(f, "{:.1}", x),
+            Expr::Float(x) if x.fract() == 0.0 => write!(f, "{:e}", x),
+            Expr::Float(x) => write!(f, "{}", x),
+            Expr::Str(s) => write!(f, "\"{}\"", escape(s)),
+            Expr::Cons(ref c1, ref c2) => match (&**c1, &**c2) {
+                (Expr::Symbol(q), Expr::Cons(e, rest))
+                    if q == "quote" && **rest == Expr::Nil =>
+                    write!(f, "'{}", e),
+                _ => {
+                    write!(f, "({}", *c1)?;
+                    fmt_cons(c2, f)
+                }
             }
         }
     }
 }
 
+// The inverse of `read_string`.
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c => out.push(c)
+        }
+    }
+    out
+}
+
 fn fmt_cons(mut cons: &Expr, f: &mut fmt::Formatter) -> fmt::Result {
     loop {
         match cons {
@@ -63,19 +100,157 @@ pub fn cdr(cons : &Expr) -> Expr {
     }
 }
 
+pub fn list_to_vec(mut cons : &Expr) -> Vec<Expr> {
+    let mut vec = Vec::new();
+    while let Expr::Cons(c1, c2) = cons {
+        vec.push((**c1).clone());
+        cons = c2;
+    }
+    vec
+}
+
+pub fn vec_to_list(vec : Vec<Expr>) -> Expr {
+    vec.into_iter().rev()
+        .fold(Expr::Nil, |cdr, car| Expr::Cons(Box::new(car), Box::new(cdr)))
+}
+
+pub fn new_symbol(s: &str) -> Expr {
+    Expr::Symbol(s.to_string())
+}
+
+// The float with the shortest decimal form giving back `x`.
+pub fn new_float(x: Float) -> Expr {
+    Expr::Float(x.to_string().parse().unwrap_or(0.0))
+}
+
+pub fn new_vec(v: &Vec3) -> Expr {
+    vec_to_list(vec![new_float(v.0), new_float(v.1), new_float(v.2)])
+}
+
+// A list starting with the symbol `head`.
+pub fn new_form(head: &str, args: Vec<Expr>) -> Expr {
+    Expr::Cons(Box::new(new_symbol(head)), Box::new(vec_to_list(args)))
+}
+
+// Prints the expression starting at column `col`. Lists too long for the
+// line, or with more than two lists in them, are broken after their
+// leading atoms and have one element per line, aligned after the head:
+//
+// (mat m1 solid
+//      (color 255.0 100.0 100.0)
+//      (spec 8.0 0.4)
+//      (reflection 0.75))
+//
+// The body of the forms such as repeat is indented by two columns
+// instead. Lists of atoms too long for the line are filled up to its end.
+pub fn pretty(expr: &Expr, col: usize) -> String {
+    let flat = expr.to_string();
+    let items = list_to_vec(expr);
+    let lists = items.iter()
+        .filter(|e| matches!(e, Expr::Cons(_, _)))
+        .count();
+    let proper = vec_to_list(items.clone()) == *expr;
+    if !proper || items.is_empty() ||
+        (col + flat.len() <= WIDTH && lists < 3) {
+        return flat;
+    }
+    let indent = match items[0] {
+        Expr::Cons(_, _) => col + 1,
+        _ => col + 2 + items[0].to_string().len()
+    };
+    if lists == 0 {
+        return fill(&items, col, indent);
+    }
+    let body = BODY_FORMS.iter().find(|(name, _)| {
+        items[0] == Expr::Symbol(name.to_string())
+    });
+    let (atoms, indent) = match body {
+        Some((_, args)) => (args + 1, col + 2),
+        // a head alone keeps its first argument on its line
+        None => (items.iter()
+                 .take_while(|e| !matches!(e, Expr::Cons(_, _)))
+                 .count()
+                 .max(2), indent)
+    };
+    let mut out = String::from("(");
+    let mut pos = col + 1;
+    for (i, e) in items.iter().enumerate() {
+        if i >= atoms {
+            out += &format!("\n{}", " ".repeat(indent));
+            pos = indent;
+        } else if i > 0 {
+            out += " ";
+            pos += 1;
+        }
+        let text = pretty(e, pos);
+        pos = match text.rfind('\n') {
+            Some(n) => text.len() - n - 1,
+            None => pos + text.len()
+        };
+        out += &text;
+    }
+    out + ")"
+}
+
+fn fill(items: &[Expr], col: usize, indent: usize) -> String {
+    let mut out = String::from("(");
+    let mut pos = col + 1;
+    for (i, e) in items.iter().enumerate() {
+        let word = e.to_string();
+        if i > 0 && pos + 1 + word.len() + 1 > WIDTH {
+            out += &format!("\n{}", " ".repeat(indent));
+            pos = indent;
+        } else if i > 0 {
+            out += " ";
+            pos += 1;
+        }
+        pos += word.len();
+        out += &word;
+    }
+    out + ")"
+}
+
 fn new_word(tok : &[char]) -> Token {
     Token::Word(tok.iter().collect())
 }
 
 fn is_sep(c : char) -> bool {
-    matches!(c, '(' | ')' | ' ' | '\t' | '\n' | '\'')
+    matches!(c, '(' | ')' | ' ' | '\t' | '\n' | '\r' | '\'' | ';' | '"')
 }
 
-pub fn tokenize(input : &str) -> Vec<Token> {
+// Reads a string up to its closing quote, with the escapes \" \\ \n, \r
+// and \t.
+fn read_string(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut s = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(s),
+            '\\' => s.push(match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some(c @ ('"' | '\\')) => c,
+                Some(c) => {
+                    println!("Parse error: unknown escape \\{}", c);
+                    return None;
+                },
+                None => break
+            }),
+            c => s.push(c)
+        }
+    }
+    println!("Parse error: unterminated string");
+    None
+}
+
+// `;` starts a comment running to the end of the line and 'e stands for
+// (quote e). None when a string is not closed or has invalid escapes.
+pub fn tokenize(input : &str) -> Option<Vec<Token>> {
     let mut vec : Vec<Token> = Vec::new();
     let mut tok : Vec<char> = Vec::new();
+    let mut chars = input.chars();
 
-    for c in input.chars() {
+    while let Some(c) = chars.next() {
         if is_sep(c) && !tok.is_empty() {
             vec.push(new_word(&tok));
             tok.clear();
@@ -83,14 +258,19 @@ pub fn tokenize(input : &str) -> Vec<Token> {
         match c {
             '(' => vec.push(Token::Lpar),
             ')' => vec.push(Token::Rpar),
-            ' ' | '\t' | '\n' => {},
+            '\'' => vec.push(Token::Quote),
+            ';' => {
+                chars.by_ref().find(|c| *c == '\n');
+            },
+            '"' => vec.push(Token::Str(read_string(&mut chars)?)),
+            ' ' | '\t' | '\n' | '\r' => {},
             _ => tok.push(c)
         }
     }
     if !tok.is_empty() {
         vec.push(new_word(&tok));
     }
-    vec
+    Some(vec)
 }
 
 fn parse_list(tokens : &[Token]) -> Option<(Expr , &[Token])> {
@@ -127,6 +307,9 @@ pub fn parse_expr(tokens : &[Token]) -> Option<(Expr, &[Token])> {
         },
         Token::Rpar => None, 
         Token::Dot => None,
+        Token::Quote => parse_expr(&tokens[1..])
+            .map(|(e, rest)| (new_form("quote", vec![e]), rest)),
+        Token::Str(s) => Some((Expr::Str(s.clone()), &tokens[1..])),
         Token::Word(w) => Some (match w.parse::<i64>() {
             Ok(n) => (Expr::Num(n), &tokens[1..]),
             Err(_) => match w.parse::<f64>() {
@@ -147,3 +330,55 @@ pub fn parse_all(tokens: &[Token]) -> Option<Vec<Expr>> {
                       .map( | e2 | [vec![e1], e2].concat()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pretty_empty_list_past_the_width() {
+        assert_eq!(pretty(&Expr::Nil, WIDTH), "()");
+    }
+
+    fn read(input: &str) -> Option<Vec<Expr>> {
+        parse_all(&tokenize(input)?)
+    }
+
+    #[test]
+    fn strings_keep_separators_and_escapes() {
+        assert_eq!(tokenize(r#""a;b \"q\" \\ \n""#),
+                   Some(vec![Token::Str("a;b \"q\" \\ \n".to_string())]));
+        assert_eq!(tokenize(r#"(x"a b")"#),
+                   Some(vec![Token::Lpar, Token::Word("x".to_string()),
+                             Token::Str("a b".to_string()), Token::Rpar]));
+        assert_eq!(tokenize(r#""\q""#), None);
+        assert_eq!(tokenize(r#""abc"#), None);
+        assert_eq!(tokenize(r#""abc\"#), None);
+    }
+
+    #[test]
+    fn carriage_returns() {
+        assert_eq!(read("(a\r\nb) ; c\r\n(d)"), read("(a b) (d)"));
+        assert_eq!(tokenize(r#""a\rb""#),
+                   Some(vec![Token::Str("a\rb".to_string())]));
+        assert_eq!(Expr::Str("a\rb".to_string()).to_string(), r#""a\rb""#);
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(read("'x"), Some(vec![new_form("quote",
+                                                  vec![new_symbol("x")])]));
+        assert_eq!(read("'(1 2)").unwrap()[0].to_string(), "'(1 2)");
+        let text = "(a 'b '(c \"d\"))";
+        let e = read(text).unwrap();
+        assert_eq!(e[0].to_string(), text);
+        assert_eq!(read(&e[0].to_string()), Some(e));
+    }
+
+    #[test]
+    fn strings_display_escaped() {
+        let s = Expr::Str("say \"hi\"\\\n\tbye".to_string());
+        assert_eq!(s.to_string(), r#""say \"hi\"\\\n\tbye""#);
+        assert_eq!(read(&s.to_string()), Some(vec![s]));
+    }
+}
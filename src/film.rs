@@ -0,0 +1,139 @@
+use std::io;
+use std::io::prelude::*;
+use crate::vec3::Float;
+use crate::checkpoint::read_u32;
+use crate::color::{Color, new_color, luminance};
+use crate::filter::Filter;
+use crate::raytracer::BLACK;
+
+// Accumulates filtered samples over the pixels [x0, x0+width) x
+// [y0, y0+height) of the image. Workers splat into small films which are
+// then merged into the one covering the whole frame.
+pub struct Film {
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    colors: Vec<Color>,
+    weights: Vec<Float>,
+    // number of samples taken in each pixel, with the sum of their
+    // luminance and of its square to estimate the noise
+    samples: Vec<u32>,
+    moments: Vec<(Float, Float)>
+}
+
+pub fn new_film(x0: u32, y0: u32, width: u32, height: u32) -> Film {
+    let size = (width * height) as usize;
+    Film { x0, y0, width, height,
+           colors: vec![BLACK ; size],
+           weights: vec![0.0 ; size],
+           samples: vec![0 ; size],
+           moments: vec![(0.0, 0.0) ; size] }
+}
+
+pub fn read_film(input: &mut dyn Read) -> io::Result<Film> {
+    let x0 = read_u32(input)?;
+    let y0 = read_u32(input)?;
+    let width = read_u32(input)?;
+    let height = read_u32(input)?;
+    let mut film = new_film(x0, y0, width, height);
+    let mut values = [0.0 ; 6];
+    for i in 0..film.colors.len() {
+        for v in values.iter_mut() {
+            *v = Float::from_bits(read_u32(input)?);
+        }
+        film.colors[i] = new_color(values[0], values[1], values[2]);
+        film.weights[i] = values[3];
+        film.moments[i] = (values[4], values[5]);
+        film.samples[i] = read_u32(input)?;
+    }
+    Ok(film)
+}
+
+impl Film {
+    // Splats a sample taken at the offset (dx, dy) in the pixel (x, y)
+    // into all the pixels within the filter radius.
+    pub fn add_sample(&mut self, filter: &Filter, (x, y): (u32, u32),
+                      (dx, dy): (Float, Float), color: Color) {
+        let (sx, sy) = (x as Float + dx, y as Float + dy);
+        let x_min = (sx - 0.5 - filter.radius).ceil().max(self.x0 as Float);
+        let y_min = (sy - 0.5 - filter.radius).ceil().max(self.y0 as Float);
+        let x_max = (sx - 0.5 + filter.radius).floor()
+            .min((self.x0 + self.width) as Float - 1.0);
+        let y_max = (sy - 0.5 + filter.radius).floor()
+            .min((self.y0 + self.height) as Float - 1.0);
+        for py in y_min as i64 ..= y_max as i64 {
+            for px in x_min as i64 ..= x_max as i64 {
+                let w = filter.weight(px as Float + 0.5 - sx,
+                                      py as Float + 0.5 - sy);
+                if w != 0.0 {
+                    let i = self.index(px as u32, py as u32);
+                    self.colors[i] = self.colors[i] + color * w;
+                    self.weights[i] += w;
+                }
+            }
+        }
+        let i = self.index(x, y);
+        let l = luminance(&color);
+        self.samples[i] += 1;
+        self.moments[i] = (self.moments[i].0 + l, self.moments[i].1 + l * l);
+    }
+
+    pub fn merge(&mut self, patch: &Film) {
+        for y in patch.y0 .. patch.y0 + patch.height {
+            for x in patch.x0 .. patch.x0 + patch.width {
+                let (i, j) = (self.index(x, y), patch.index(x, y));
+                self.colors[i] = self.colors[i] + patch.colors[j];
+                self.weights[i] += patch.weights[j];
+                self.samples[i] += patch.samples[j];
+                self.moments[i] = (self.moments[i].0 + patch.moments[j].0,
+                                   self.moments[i].1 + patch.moments[j].1);
+            }
+        }
+    }
+
+    pub fn resolve(&self) -> Vec<Color> {
+        self.colors.iter().zip(self.weights.iter())
+            .map(|(c, w)| if *w > 0.0 { *c / *w } else { BLACK })
+            .collect()
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        for v in [self.x0, self.y0, self.width, self.height] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        for i in 0..self.colors.len() {
+            let (c, (m0, m1)) = (self.colors[i], self.moments[i]);
+            for v in [c.0, c.1, c.2, self.weights[i], m0, m1] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+            out.write_all(&self.samples[i].to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Mean relative standard error of the pixel luminances, infinite until
+    // some pixels got at least two samples.
+    pub fn noise(&self) -> Float {
+        let (mut total, mut n) = (0.0, 0);
+        for (count, (s, s2)) in self.samples.iter().zip(self.moments.iter()) {
+            if *count < 2 || *s <= 0.0 {
+                continue;
+            }
+            let count = *count as Float;
+            let mean = s / count;
+            let variance = ((s2 - s * mean) / (count - 1.0)).max(0.0);
+            total += (variance / count).sqrt() / mean;
+            n += 1;
+        }
+        if n == 0 {
+            Float::INFINITY
+        } else {
+            total / n as Float
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y0) * self.width + x - self.x0) as usize
+    }
+}
@@ -0,0 +1,140 @@
+use std::f32::consts::PI;
+use crate::vec3::Float;
+
+const GAUSSIAN_ALPHA : Float = 2.0;
+const MITCHELL_B : Float = 1.0 / 3.0;
+const MITCHELL_C : Float = 1.0 / 3.0;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos
+}
+
+// Reconstruction filter, `radius` is in pixels.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: Float
+}
+
+// A half pixel box is a plain average of the samples of each pixel.
+pub const DEFAULT_FILTER : Filter = Filter {
+    kind: FilterKind::Box,
+    radius: 0.5
+};
+
+pub fn get_filter_kind(name: &str) -> Option<FilterKind> {
+    match name {
+        "box" => Some(FilterKind::Box),
+        "tent" => Some(FilterKind::Tent),
+        "gaussian" => Some(FilterKind::Gaussian),
+        "mitchell" => Some(FilterKind::Mitchell),
+        "lanczos" => Some(FilterKind::Lanczos),
+        _ => None
+    }
+}
+
+pub fn filter_kind_name(kind: FilterKind) -> &'static str {
+    match kind {
+        FilterKind::Box => "box",
+        FilterKind::Tent => "tent",
+        FilterKind::Gaussian => "gaussian",
+        FilterKind::Mitchell => "mitchell",
+        FilterKind::Lanczos => "lanczos"
+    }
+}
+
+impl Filter {
+    // Weight of a sample at offset (dx, dy) from the pixel center.
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        self.weight_1d(dx.abs()) * self.weight_1d(dy.abs())
+    }
+
+    fn weight_1d(&self, d: Float) -> Float {
+        let r = self.radius;
+        if d >= r {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - d / r,
+            FilterKind::Gaussian =>
+                ((-GAUSSIAN_ALPHA * d * d).exp() -
+                 (-GAUSSIAN_ALPHA * r * r).exp()).max(0.0),
+            FilterKind::Mitchell => mitchell(2.0 * d / r),
+            FilterKind::Lanczos => sinc(d) * sinc(d / r)
+        }
+    }
+}
+
+fn mitchell(x: Float) -> Float {
+    let (b, c) = (MITCHELL_B, MITCHELL_C);
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x +
+         (-18.0 + 12.0 * b + 6.0 * c) * x * x +
+         (6.0 - 2.0 * b)) / 6.0
+    } else {
+        ((-b - 6.0 * c) * x * x * x +
+         (6.0 * b + 30.0 * c) * x * x +
+         (-12.0 * b - 48.0 * c) * x +
+         (8.0 * b + 24.0 * c)) / 6.0
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::new_film;
+    use crate::color::new_color;
+
+    const KINDS : [FilterKind ; 5] = [FilterKind::Box, FilterKind::Tent,
+                                     FilterKind::Gaussian,
+                                     FilterKind::Mitchell,
+                                     FilterKind::Lanczos];
+
+    #[test]
+    fn weights_vanish_past_the_radius() {
+        for kind in KINDS {
+            let filter = Filter { kind, radius: 1.5 };
+            assert!(filter.weight(0.0, 0.0) > 0.0, "{:?}", kind);
+            assert_eq!(filter.weight(0.3, -0.2), filter.weight(-0.3, 0.2));
+            for d in [1.5, 1.6, 3.0] {
+                assert_eq!(filter.weight(d, 0.0), 0.0, "{:?}", kind);
+                assert_eq!(filter.weight(0.0, -d), 0.0, "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn film_divides_by_the_weights() {
+        // a flat color stays the same whatever the filter
+        for kind in KINDS {
+            let filter = Filter { kind, radius: 1.5 };
+            let mut film = new_film(0, 0, 4, 4);
+            for y in 0..4 {
+                for x in 0..4 {
+                    for d in [(0.25, 0.25), (0.75, 0.5), (0.1, 0.9)] {
+                        film.add_sample(&filter, (x, y), d,
+                                        new_color(0.2, 0.4, 0.8));
+                    }
+                }
+            }
+            for c in film.resolve() {
+                assert!((c.0 - 0.2).abs() < 1e-5, "{:?}", kind);
+                assert!((c.2 - 0.8).abs() < 1e-5, "{:?}", kind);
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::expr::{parse_all, tokenize, pretty};
+
+// The top level of a scene file, in the order of the source.
+enum Item {
+    Blank,
+    Comment(String),
+    // the text of the form, with the comment ending its last line if any
+    Form(String, Option<String>)
+}
+
+// Formats a scene file: each form is pretty-printed, the comments are
+// kept and the blank lines between the forms are reduced to one. The
+// forms are not evaluated. A form with comments inside is left as it is
+// written, the printer not knowing where to put them. Formatting twice
+// gives the same text.
+pub fn format_scene(source: &str) -> Option<String> {
+    let mut out = String::new();
+    for item in read_items(source)? {
+        match item {
+            Item::Blank => {},
+            Item::Comment(comment) => out += &comment,
+            Item::Form(text, comment) => {
+                out += &text;
+                if let Some(comment) = comment {
+                    out += &format!(" {}", comment);
+                }
+            }
+        }
+        out.push('\n');
+    }
+    Some(out)
+}
+
+fn read_items(source: &str) -> Option<Vec<Item>> {
+    let mut items = Vec::new();
+    // text of the form being read, and whether it has comments
+    let mut form = String::new();
+    let mut commented = false;
+    let mut depth = 0;
+    // line breaks since the last item
+    let mut newlines = 0;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        // a top level atom ends at the first separator, a quote going with
+        // the expression after it
+        if depth == 0 && !form.is_empty() && !form.ends_with('\'') &&
+            (c.is_whitespace() || matches!(c, '(' | ')' | ';' | '"')) {
+            push_form(&mut items, &form, commented)?;
+            form.clear();
+            newlines = 0;
+        }
+        match c {
+            ';' => {
+                let mut comment = String::from(";");
+                while let Some(c) = chars.next_if(|c| *c != '\n') {
+                    comment.push(c);
+                }
+                let comment = comment.trim_end().to_string();
+                match items.last_mut() {
+                    _ if depth > 0 => {
+                        form += &comment;
+                        commented = true;
+                    },
+                    Some(Item::Form(_, end @ None)) if newlines == 0 =>
+                        *end = Some(comment),
+                    _ => {
+                        separate(&mut items, newlines);
+                        items.push(Item::Comment(comment));
+                        newlines = 0;
+                    }
+                }
+            },
+            '\n' if depth == 0 => newlines += 1,
+            c if c.is_whitespace() && depth == 0 => {},
+            c => {
+                if depth == 0 && form.is_empty() {
+                    separate(&mut items, newlines);
+                    newlines = 0;
+                }
+                form.push(c);
+                if c == '"' {
+                    // copied as it is up to its end, escapes included
+                    while let Some(c) = chars.next() {
+                        form.push(c);
+                        match c {
+                            '\\' => form.extend(chars.next()),
+                            '"' => break,
+                            _ => {}
+                        }
+                    }
+                } else if c == '(' {
+                    depth += 1;
+                } else if c == ')' {
+                    if depth == 0 {
+                        println!("Parse error: unexpected )");
+                        return None;
+                    }
+                    depth -= 1;
+                    if depth == 0 {
+                        push_form(&mut items, &form, commented)?;
+                        form.clear();
+                        commented = false;
+                    }
+                }
+            }
+        }
+    }
+    if depth > 0 {
+        println!("Parse error: missing ) in {}", form.trim_end());
+        return None;
+    }
+    if !form.is_empty() {
+        push_form(&mut items, &form, commented)?;
+    }
+    Some(items)
+}
+
+// Keeps one blank line where the source has some between two items.
+fn separate(items: &mut Vec<Item>, newlines: usize) {
+    if newlines > 1 && !items.is_empty() {
+        items.push(Item::Blank);
+    }
+}
+
+fn push_form(items: &mut Vec<Item>, text: &str, commented: bool)
+             -> Option<()> {
+    let exprs = tokenize(text).and_then(|tokens| parse_all(&tokens))
+        .filter(|e| e.len() == 1);
+    let Some(expr) = exprs.and_then(|mut e| e.pop()) else {
+        println!("Parse error: {}", text);
+        return None;
+    };
+    let text = if commented { text.to_string() } else { pretty(&expr, 0) };
+    items.push(Item::Form(text, None));
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_scene(source).unwrap()
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        assert_eq!(format("; scene\n(camera   800 600 400)   ; size\n"),
+                   "; scene\n(camera 800 600 400) ; size\n");
+        assert_eq!(format("(eye (0 0 0))\n; below\n(light (1 1 1))"),
+                   "(eye (0 0 0))\n; below\n(light (1 1 1))\n");
+    }
+
+    #[test]
+    fn commented_forms_are_left_as_written() {
+        let source = "(mat m solid   ; red\n     (color 255 0 0))\n";
+        assert_eq!(format(source), source);
+    }
+
+    #[test]
+    fn blank_lines_are_collapsed() {
+        assert_eq!(format("\n\n(eye (0 0 0))\n\n\n\n(light (1 1 1))\n\n"),
+                   "(eye (0 0 0))\n\n(light (1 1 1))\n");
+        assert_eq!(format("(eye (0 0 0)) (light (1 1 1))"),
+                   "(eye (0 0 0))\n(light (1 1 1))\n");
+    }
+
+    #[test]
+    fn formatting_twice_gives_the_same_text() {
+        let source = include_str!("../test.conf");
+        let once = format(source);
+        assert_eq!(format(&once), once);
+        let odd = "'x ; q\n\n\n(a\n\"b ; c\"\n)(d;e\n)  f\n";
+        let once = format(odd);
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn unbalanced_forms_fail() {
+        assert!(format_scene("(a (b)").is_none());
+        assert!(format_scene("(a))").is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::File;
+use crate::color::Color;
+
+type OutResult = Result<(), io::Error>;
+
+pub fn is_hdr(filename: &str) -> bool {
+    filename.ends_with(".pfm") || filename.ends_with(".exr")
+}
+
+// The frame is written as is, in linear light.
+pub fn write_hdr(frame: &[Color], width: u32, height: u32, filename: &str)
+                 -> OutResult {
+    if filename.ends_with(".exr") {
+        write_exr(frame, width, height, filename)
+    } else {
+        write_pfm(frame, width, height, filename)
+    }
+}
+
+// Portable float map: little endian (negative scale), bottom row first.
+fn write_pfm(frame: &[Color], width: u32, height: u32, filename: &str)
+             -> OutResult {
+    let mut out = BufWriter::new(File::create(filename)?);
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for y in (0..height).rev() {
+        for c in &frame[(y * width) as usize .. ((y + 1) * width) as usize] {
+            for v in [c.0, c.1, c.2] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+fn attribute(out: &mut dyn Write, name: &str, kind: &str, value: &[u8])
+             -> OutResult {
+    out.write_all(name.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(kind.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(&(value.len() as i32).to_le_bytes())?;
+    out.write_all(value)
+}
+
+fn ints(values: &[i32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn floats(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+// Single part scanline OpenEXR, uncompressed 32 bits float RGB, one line
+// per chunk.
+fn write_exr(frame: &[Color], width: u32, height: u32, filename: &str)
+             -> OutResult {
+    let mut header : Vec<u8> = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    // channels are stored in alphabetical order, as FLOAT (2)
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend(ints(&[2, 0, 1, 1]));
+    }
+    channels.push(0);
+    let window = ints(&[0, 0, width as i32 - 1, height as i32 - 1]);
+    attribute(&mut header, "channels", "chlist", &channels)?;
+    attribute(&mut header, "compression", "compression", &[0])?;
+    attribute(&mut header, "dataWindow", "box2i", &window)?;
+    attribute(&mut header, "displayWindow", "box2i", &window)?;
+    attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    attribute(&mut header, "pixelAspectRatio", "float", &floats(&[1.0]))?;
+    attribute(&mut header, "screenWindowCenter", "v2f",
+              &floats(&[0.0, 0.0]))?;
+    attribute(&mut header, "screenWindowWidth", "float", &floats(&[1.0]))?;
+    header.push(0);
+
+    let line_size = (width * 3 * 4) as u64;
+    let table_size = u64::from(height) * 8;
+    let first = header.len() as u64 + table_size;
+    let mut out = BufWriter::new(File::create(filename)?);
+    out.write_all(&header)?;
+    for y in 0..u64::from(height) {
+        out.write_all(&(first + y * (8 + line_size)).to_le_bytes())?;
+    }
+    for y in 0..height {
+        let line = &frame[(y * width) as usize .. ((y + 1) * width) as usize];
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        for channel in [2, 1, 0] {
+            for c in line {
+                let v = [c.0, c.1, c.2][channel];
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
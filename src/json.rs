@@ -0,0 +1,498 @@
+use std::fs;
+use std::path::Path;
+use crate::expr::{Expr, vec_to_list, new_symbol, new_form};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    // the members in the order of the source
+    Object(Vec<(String, Json)>)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize
+}
+
+pub fn parse_json(source: &str) -> Option<Json> {
+    let mut parser = Parser { chars: source.chars().collect(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_space();
+    if parser.pos < parser.chars.len() {
+        return parser.error("end of input");
+    }
+    Some(value)
+}
+
+impl Parser {
+    fn error<T>(&self, expected: &str) -> Option<T> {
+        let line = self.chars[..self.pos.min(self.chars.len())].iter()
+            .filter(|c| **c == '\n')
+            .count() + 1;
+        println!("JSON error line {}: expected {}", line, expected);
+        None
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.skip_space();
+        if self.peek() != Some(c) {
+            return self.error(&format!("'{}'", c));
+        }
+        self.pos += 1;
+        Some(())
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_space();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::Str),
+            Some('-' | '0'..='9') => self.number(),
+            Some(_) => {
+                for (word, value) in [("true", Json::Bool(true)),
+                                      ("false", Json::Bool(false)),
+                                      ("null", Json::Null)] {
+                    let end = self.pos + word.len();
+                    if self.chars.get(self.pos..end)
+                        .is_some_and(|w| w.iter().copied().eq(word.chars())) {
+                        self.pos = end;
+                        return Some(value);
+                    }
+                }
+                self.error("a value")
+            },
+            None => self.error("a value")
+        }
+    }
+
+    // The members or elements up to `close`, separated by commas.
+    fn sequence<T>(&mut self, close: char,
+                   mut item: impl FnMut(&mut Parser) -> Option<T>)
+                   -> Option<Vec<T>> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_space();
+        if self.peek() == Some(close) {
+            self.pos += 1;
+            return Some(items);
+        }
+        loop {
+            items.push(item(self)?);
+            self.skip_space();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    return Some(items);
+                },
+                _ => return self.error(&format!("',' or '{}'", close))
+            }
+        }
+    }
+
+    fn object(&mut self) -> Option<Json> {
+        let members = self.sequence('}', |p| {
+            p.skip_space();
+            if p.peek() != Some('"') {
+                return p.error("a member name");
+            }
+            let name = p.string()?;
+            p.expect(':')?;
+            Some((name, p.value()?))
+        })?;
+        Some(Json::Object(members))
+    }
+
+    fn array(&mut self) -> Option<Json> {
+        self.sequence(']', |p| p.value()).map(Json::Array)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.error("'\"'")
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Some(s),
+                '\\' => s.push(self.escape()?),
+                c => s.push(c)
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        let c = match c {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let high = self.hex()?;
+                // characters out of the basic plane come as two halves
+                let code = if (0xd800..0xdc00).contains(&high) &&
+                    self.chars.get(self.pos..self.pos + 2) ==
+                    Some(&['\\', 'u']) {
+                    self.pos += 2;
+                    let low = self.hex()?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return self.error("a low surrogate");
+                    }
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    high
+                };
+                return char::from_u32(code)
+                    .or_else(|| self.error("a valid character"));
+            },
+            _ => return self.error("an escape sequence")
+        };
+        Some(c)
+    }
+
+    fn hex(&mut self) -> Option<u32> {
+        let digits : String = self.chars.iter().skip(self.pos).take(4)
+            .collect();
+        match u32::from_str_radix(&digits, 16) {
+            Ok(n) if digits.len() == 4 => {
+                self.pos += 4;
+                Some(n)
+            },
+            _ => self.error("4 hexadecimal digits")
+        }
+    }
+
+    // Numbers written without a fraction or an exponent are integers.
+    fn number(&mut self) -> Option<Json> {
+        let start = self.pos;
+        while matches!(self.peek(),
+                       Some('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+            self.pos += 1;
+        }
+        let text : String = self.chars[start..self.pos].iter().collect();
+        if let Ok(n) = text.parse() {
+            return Some(Json::Num(n));
+        }
+        match text.parse() {
+            Ok(x) => Some(Json::Float(x)),
+            Err(_) => {
+                self.pos = start;
+                self.error("a number")
+            }
+        }
+    }
+}
+
+// The settings given as objects, with their members in the order of the
+// arguments of their forms. The members may be omitted from the end.
+const SETTINGS : [(&str, &[&str]) ; 8] = [
+    ("camera", &["width", "height", "depth"]),
+    ("aperture", &["size", "blades"]),
+    ("shutter", &["open", "close"]),
+    ("sampler", &["kind", "samples", "threshold"]),
+    ("filter", &["kind", "radius"]),
+    ("tiles", &["size", "order"]),
+    ("progressive", &["samples", "time", "noise"]),
+    ("denoise", &["kind", "radius"])
+];
+
+// The settings given as a single value.
+const VALUES : [&str ; 6] = ["reflections", "eye", "focus-distance", "light",
+                             "tonemap", "exposure"];
+
+const EFFECTS : [(&str, &[&str]) ; 4] = [
+    ("bloom", &["threshold", "radius"]),
+    ("vignette", &["strength"]),
+    ("chromatic-aberration", &["amount"]),
+    ("grain", &["amount"])
+];
+
+// Reads a JSON scene as the forms of the scene language, in the order
+// they must be loaded in: the settings, the materials, the groups and the
+// objects. The format is described by scene.schema.json.
+pub fn read_json_scene(path: &Path) -> Option<Vec<Expr>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            println!("unable to read {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    let members = match parse_json(&contents)? {
+        Json::Object(members) => members,
+        _ => return json_error("the scene must be an object")
+    };
+    let mut forms = Vec::new();
+    for (name, value) in &members {
+        let name = name.as_str();
+        if let Some((_, fields)) = SETTINGS.iter().find(|s| s.0 == name) {
+            forms.push(new_form(name, get_fields(name, value, fields)?));
+        } else if VALUES.contains(&name) {
+            forms.push(new_form(name, vec![get_value(value)?]));
+        } else if name == "post" {
+            let effects = get_array(name, value)?.iter()
+                .map(get_effect)
+                .collect::<Option<Vec<Expr>>>()?;
+            forms.push(new_form("post", effects));
+        } else if !["materials", "groups", "objects"].contains(&name) {
+            return json_error(&format!("unexpected member {}", name));
+        }
+    }
+    let get = |name| members.iter().rev().find(|(n, _)| n == name)
+        .map_or(Some(&[][..]), |(_, value)| get_array(name, value));
+    for material in get("materials")? {
+        forms.push(get_tagged(material, "mat", &["name", "shader"])?);
+    }
+    for group in get("groups")? {
+        let name = get_member(group, "name")?;
+        let objects = match get_member(group, "objects")? {
+            Json::Array(objects) => objects,
+            _ => return json_error("the objects of a group must be an array")
+        };
+        let mut args = vec![get_value(name)?];
+        for object in objects {
+            args.push(get_object(object)?);
+        }
+        forms.push(new_form("group", args));
+    }
+    for object in get("objects")? {
+        forms.push(get_object(object)?);
+    }
+    Some(forms)
+}
+
+fn json_error<T>(message: &str) -> Option<T> {
+    println!("JSON error: {}", message);
+    None
+}
+
+fn get_array<'a>(name: &str, value: &'a Json) -> Option<&'a [Json]> {
+    match value {
+        Json::Array(values) => Some(values),
+        _ => json_error(&format!("{} must be an array", name))
+    }
+}
+
+fn get_member<'a>(value: &'a Json, name: &str) -> Option<&'a Json> {
+    let member = match value {
+        Json::Object(members) => members.iter().rev().find(|m| m.0 == name),
+        _ => return json_error(&format!("expected an object with {}", name))
+    };
+    match member {
+        Some((_, value)) => Some(value),
+        None => json_error(&format!("missing {} in {:?}", name, value))
+    }
+}
+
+// Numbers, names and lists of them.
+fn get_value(value: &Json) -> Option<Expr> {
+    match value {
+        Json::Null => Some(Expr::Nil),
+        Json::Num(n) => Some(Expr::Num(*n)),
+        Json::Float(x) => Some(Expr::Float(*x)),
+        Json::Str(s) => Some(new_symbol(s)),
+        Json::Array(values) => values.iter()
+            .map(get_value)
+            .collect::<Option<Vec<Expr>>>()
+            .map(vec_to_list),
+        _ => json_error(&format!("unexpected {:?}", value))
+    }
+}
+
+// The members of an object as positional arguments, the missing ones in
+// the middle being ().
+fn get_fields(name: &str, value: &Json, fields: &[&str])
+              -> Option<Vec<Expr>> {
+    let Json::Object(members) = value else {
+        return json_error(&format!("{} must be an object", name));
+    };
+    if let Some((m, _)) = members.iter().find(|m| !fields.contains(&&*m.0)) {
+        return json_error(&format!("unexpected member {} in {}", m, name));
+    }
+    let mut args = fields.iter()
+        .map(|field| match members.iter().rev().find(|m| m.0 == *field) {
+            Some((_, value)) => get_value(value),
+            None => Some(Expr::Nil)
+        })
+        .collect::<Option<Vec<Expr>>>()?;
+    while args.last() == Some(&Expr::Nil) {
+        args.pop();
+    }
+    Some(args)
+}
+
+// (head tag... :member value...), the `tags` members coming first and
+// the others as keyword arguments.
+fn get_tagged(value: &Json, head: &str, tags: &[&str]) -> Option<Expr> {
+    let Json::Object(members) = value else {
+        return json_error(&format!("expected an object for {}", head));
+    };
+    let mut args = tags.iter()
+        .map(|tag| get_value(get_member(value, tag)?))
+        .collect::<Option<Vec<Expr>>>()?;
+    for (name, value) in members {
+        if !tags.contains(&name.as_str()) {
+            args.push(new_symbol(&format!(":{}", name)));
+            args.push(get_value(value)?);
+        }
+    }
+    Some(new_form(head, args))
+}
+
+// {"type": "sphere" | "plane", ...} or
+// {"type": "instance", "group": name, "transform": [{"op": args}...]}
+fn get_object(value: &Json) -> Option<Expr> {
+    let kind = match get_member(value, "type")? {
+        Json::Str(kind) => kind.as_str(),
+        _ => return json_error("the type of an object must be a string")
+    };
+    if kind != "instance" {
+        return get_tagged(&without(value, "type"), kind, &[]);
+    }
+    let mut args = vec![get_value(get_member(value, "group")?)?];
+    if let Json::Object(members) = value {
+        if let Some((_, ops)) = members.iter().find(|m| m.0 == "transform") {
+            let ops = get_array("transform", ops)?.iter()
+                .map(get_transform)
+                .collect::<Option<Vec<Expr>>>()?;
+            args.push(new_form("transform", ops));
+        }
+    }
+    Some(new_form("instance", args))
+}
+
+// {"translate": [x, y, z]} as (translate x y z), {"scale": 2} as (scale 2)
+fn get_transform(value: &Json) -> Option<Expr> {
+    let (op, arg) = match value {
+        Json::Object(members) if members.len() == 1 => &members[0],
+        _ => return json_error("a transform must have one member")
+    };
+    let args = match get_value(arg)? {
+        e @ (Expr::Cons(_, _) | Expr::Nil) => e,
+        e => vec_to_list(vec![e])
+    };
+    Some(Expr::Cons(Box::new(new_symbol(op)), Box::new(args)))
+}
+
+// {"effect": name, ...} with the members of the effect.
+fn get_effect(value: &Json) -> Option<Expr> {
+    let name = match get_member(value, "effect")? {
+        Json::Str(name) => name.as_str(),
+        _ => return json_error("the name of an effect must be a string")
+    };
+    let Some((_, fields)) = EFFECTS.iter().find(|e| e.0 == name) else {
+        return json_error(&format!("unknown effect {}", name));
+    };
+    Some(new_form(name, get_fields(name, &without(value, "effect"),
+                                   fields)?))
+}
+
+fn without(value: &Json, name: &str) -> Json {
+    match value {
+        Json::Object(members) => Json::Object(members.iter()
+                                              .filter(|m| m.0 != name)
+                                              .cloned()
+                                              .collect()),
+        _ => value.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::load_scene;
+    use crate::testutil::{temp_file, written};
+
+    #[test]
+    fn strings_and_escapes() {
+        let json = r#"["a\"b\\c\/d", "\b\f\n\r\t", "é😀"]"#;
+        assert_eq!(parse_json(json),
+                   Some(Json::Array(vec![
+                       Json::Str("a\"b\\c/d".to_string()),
+                       Json::Str("\u{8}\u{c}\n\r\t".to_string()),
+                       Json::Str("\u{e9}\u{1f600}".to_string())])));
+        assert_eq!(parse_json(r#""\ud800\u0041""#), None);
+        assert_eq!(parse_json(r#""\ud800""#), None);
+        assert_eq!(parse_json(r#""\q""#), None);
+        assert_eq!(parse_json(r#""\u12""#), None);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_json("42"), Some(Json::Num(42)));
+        assert_eq!(parse_json("-7"), Some(Json::Num(-7)));
+        assert_eq!(parse_json("0.5"), Some(Json::Float(0.5)));
+        assert_eq!(parse_json("-1.5e2"), Some(Json::Float(-150.0)));
+        assert_eq!(parse_json("1E3"), Some(Json::Float(1000.0)));
+        assert_eq!(parse_json("-"), None);
+        assert_eq!(parse_json("1.2.3"), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_json(""), None);
+        assert_eq!(parse_json("[1, 2"), None);
+        assert_eq!(parse_json("[1 2]"), None);
+        assert_eq!(parse_json(r#"{"a" 1}"#), None);
+        assert_eq!(parse_json(r#"{"a": 1,}"#), None);
+        assert_eq!(parse_json("[1] 2"), None);
+        assert_eq!(parse_json("nul"), None);
+    }
+
+    #[test]
+    fn json_scene_loads_as_its_conf() {
+        let json = r#"{
+          "reflections": 5,
+          "camera": {"width": 3840, "height": 2160, "depth": 1800},
+          "materials": [
+            {"name": "m1", "shader": "solid", "color": [255.0, 100.0, 100.0],
+             "spec": [8.0, 0.4], "reflection": 0.75},
+            {"name": "m2", "shader": "checkboard",
+             "color1": [150.0, 150.0, 225.0], "color2": [200.0, 200.0, 300.0],
+             "scale": 10, "spec": [4.0, 0.4], "reflection": 0.25}
+          ],
+          "objects": [
+            {"type": "sphere", "center": [-2.0, -5.0, 30.0], "radius": 5.0,
+             "material": "m1"},
+            {"type": "sphere", "center": [8.0, 1.0, 30.0], "radius": 5.0,
+             "material": "m1"},
+            {"type": "sphere", "center": [8.0, -10.0, 20.0], "radius": 5.0,
+             "material": "m1"},
+            {"type": "sphere", "center": [-3.0, 2.0, 10.0], "radius": 3.0,
+             "material": "m1"},
+            {"type": "plane", "point": [0.0, 3.001, 0.0],
+             "normal": [0.0, 1.0, 0.0], "material": "m2"}
+          ]
+        }"#;
+        let file = temp_file("json");
+        fs::write(&file.path, json).unwrap();
+        let from_json = load_scene(file.name(), 0).unwrap();
+        let conf = concat!(env!("CARGO_MANIFEST_DIR"), "/test.conf");
+        assert_eq!(written(&from_json), written(&load_scene(conf, 0).unwrap()));
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use crate::vec3::{Vec3, Float, Norm};
+use crate::expr::{Expr, car, cdr, list_to_vec};
+use crate::scene::{get_float, get_symbol, get_vec, get_transforms,
+                   get_material, get_object_args, read_forms};
+use crate::transform::NO_TRANSFORM;
+
+// Bounding box of an object, as its lowest and highest corners.
+type Bounds = (Vec3, Vec3);
+
+struct Lint {
+    warnings: Vec<String>,
+    // in the order of their definitions, and whether they are used, a
+    // material defined again shadowing the previous one
+    materials: Vec<(String, bool)>,
+    groups: HashMap<String, Vec<Expr>>
+}
+
+// Checks the scene as loaded for its first frame, after the expansion of
+// the includes and loops, and returns the warnings found: unused,
+// undefined or duplicate names, degenerate objects and objects behind the
+// camera. None when the scene cannot be read.
+pub fn lint_scene(filename: &str) -> Option<Vec<String>> {
+    let forms = read_forms(filename, 0)?;
+    let mut lint = Lint { warnings: Vec::new(),
+                          materials: Vec::new(),
+                          groups: HashMap::new() };
+    // the last eye given is the one used
+    let eye = forms.iter().rev()
+        .filter(|form| car(form) == Expr::Symbol("eye".to_string()))
+        .find_map(|form| get_vec(&car(&cdr(form))))
+        .unwrap_or(Vec3(0.0, 0.0, 0.0));
+    for form in &forms {
+        let head = get_symbol(&car(form)).unwrap_or_default();
+        match head.as_str() {
+            "mat" => lint.check_material(form),
+            "group" => lint.check_group(form),
+            "sphere" | "plane" | "instance" => {
+                let bounds = lint.check_object(form)
+                    .then(|| lint.get_bounds(form))
+                    .flatten();
+                // the camera looks along z
+                if bounds.is_some_and(|(_, high)| high.2 <= eye.2) {
+                    lint.warn(format!("object behind the camera: {}", form));
+                }
+            },
+            _ => {}
+        }
+    }
+    for (name, used) in &lint.materials {
+        if !used {
+            lint.warnings.push(format!("unused material {}", name));
+        }
+    }
+    Some(lint.warnings)
+}
+
+impl Lint {
+    fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    fn check_material(&mut self, form: &Expr) {
+        // read as the loader does
+        let Some((name, _)) = get_material(&cdr(form)) else {
+            return self.warn(format!("invalid material: {}", form));
+        };
+        if self.materials.iter().any(|(n, _)| *n == name) {
+            self.warn(format!("duplicate material {}", name));
+        }
+        self.materials.push((name, false));
+    }
+
+    fn check_group(&mut self, form: &Expr) {
+        let Some(name) = get_symbol(&car(&cdr(form))) else {
+            return self.warn(format!("invalid group: {}", form));
+        };
+        if self.groups.contains_key(&name) {
+            self.warn(format!("duplicate group {}", name));
+        }
+        let objects = list_to_vec(&cdr(&cdr(form))).into_iter()
+            .filter(|object| self.check_object(object))
+            .collect();
+        self.groups.insert(name, objects);
+    }
+
+    // Whether the object could be read.
+    fn check_object(&mut self, form: &Expr) -> bool {
+        let head = get_symbol(&car(form)).unwrap_or_default();
+        let Some(args) = get_object_args(&head, &cdr(form)) else {
+            self.warn(format!("invalid object: {}", form));
+            return false;
+        };
+        let args = list_to_vec(&args);
+        let geometry = match head.as_str() {
+            "sphere" => args.first().and_then(get_vec).is_some() &&
+                args.get(1).and_then(get_float).is_some(),
+            "plane" => args.first().and_then(get_vec).is_some() &&
+                args.get(1).and_then(get_vec).is_some(),
+            _ => true
+        };
+        if !geometry {
+            self.warn(format!("invalid object: {}", form));
+            return false;
+        }
+        match head.as_str() {
+            "sphere" => {
+                match args.get(1).and_then(get_float) {
+                    Some(0.0) =>
+                        self.warn(format!("zero radius in {}", form)),
+                    Some(r) if r < 0.0 =>
+                        self.warn(format!("negative radius in {}", form)),
+                    _ => {}
+                }
+            },
+            "plane" => {
+                if args.get(1).and_then(get_vec)
+                    .is_some_and(|n| n.norm() < Float::EPSILON) {
+                    self.warn(format!("degenerate normal in {}", form));
+                }
+            },
+            "instance" => {
+                let name = args.first().and_then(get_symbol)
+                    .unwrap_or_default();
+                if !self.groups.contains_key(&name) {
+                    self.warn(format!("undefined group {} in {}", name,
+                                      form));
+                }
+                return true;
+            },
+            _ => {
+                self.warn(format!("invalid object: {}", form));
+                return false;
+            }
+        }
+        // the material comes after the geometry
+        let name = args.get(2).and_then(get_symbol).unwrap_or_default();
+        match self.materials.iter_mut().rev().find(|(n, _)| *n == name) {
+            Some((_, used)) => *used = true,
+            None => self.warn(format!("undefined material {} in {}", name,
+                                      form))
+        }
+        true
+    }
+
+    // The box around an object, None for the planes, which are unbounded.
+    fn get_bounds(&self, form: &Expr) -> Option<Bounds> {
+        let head = get_symbol(&car(form))?;
+        let args = list_to_vec(&get_object_args(&head, &cdr(form))?);
+        match head.as_str() {
+            "sphere" => {
+                let center = get_vec(args.first()?)?;
+                let r = get_float(args.get(1)?)?.abs();
+                // a moving sphere sweeps the box of both its positions
+                let end = args.get(3).and_then(|m| get_vec(&cdr(m)))
+                    .unwrap_or(center);
+                Some((min(center, end) - Vec3(r, r, r),
+                      max(center, end) + Vec3(r, r, r)))
+            },
+            "instance" => {
+                let objects = self.groups.get(&get_symbol(args.first()?)?)?;
+                let t = match args.get(1) {
+                    None => NO_TRANSFORM,
+                    Some(t) => get_transforms(t)?
+                };
+                // the box around the transformed corners of the boxes
+                objects.iter()
+                    .map(|object| {
+                        let (low, high) = self.get_bounds(object)?;
+                        (0..8).map(|i| {
+                            let pick = |bit, a, b| if i & bit == 0 { a }
+                                                   else { b };
+                            let p = t.point(&Vec3(pick(1, low.0, high.0),
+                                                  pick(2, low.1, high.1),
+                                                  pick(4, low.2, high.2)));
+                            (p, p)
+                        })
+                        .reduce(merge)
+                    })
+                    .collect::<Option<Vec<Bounds>>>()?
+                    .into_iter()
+                    .reduce(merge)
+            },
+            _ => None
+        }
+    }
+}
+
+fn merge((l1, h1): Bounds, (l2, h2): Bounds) -> Bounds {
+    (min(l1, l2), max(h1, h2))
+}
+
+fn min(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3(a.0.min(b.0), a.1.min(b.1), a.2.min(b.2))
+}
+
+fn max(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3(a.0.max(b.0), a.1.max(b.1), a.2.max(b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::testutil::temp_file;
+
+    const HEADER : &str = "(camera 80 60 40)
+(mat m solid (color 255 0 0) (spec 1.0 0.0) (reflection 0.0))
+";
+
+    fn lint(scene: &str) -> Vec<String> {
+        let file = temp_file("conf");
+        fs::write(&file.path, format!("{}{}", HEADER, scene)).unwrap();
+        lint_scene(file.name()).unwrap()
+    }
+
+    #[test]
+    fn clean_scene() {
+        assert!(lint("(sphere (0 0 30) 5 m)").is_empty());
+    }
+
+    #[test]
+    fn materials() {
+        assert_eq!(lint(""), ["unused material m"]);
+        assert_eq!(lint("(sphere (0 0 30) 5 n)"),
+                   ["undefined material n in (sphere (0 0 30) 5 n)",
+                    "unused material m"]);
+        // the first m is shadowed before any use
+        assert_eq!(lint(&format!("{}(sphere (0 0 30) 5 m)", HEADER)),
+                   ["duplicate material m", "unused material m"]);
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(lint("(group g (sphere (0 0 0) 1 m))
+                         (group g (sphere (0 0 0) 2 m))
+                         (instance g (transform (translate 0 0 30)))"),
+                   ["duplicate group g"]);
+        assert_eq!(lint("(sphere (0 0 30) 5 m) (instance h)"),
+                   ["undefined group h in (instance h)"]);
+    }
+
+    #[test]
+    fn degenerate_objects() {
+        assert_eq!(lint("(sphere (0 0 30) 0 m)"),
+                   ["zero radius in (sphere (0 0 30) 0 m)"]);
+        assert_eq!(lint("(sphere (0 0 30) -2 m)"),
+                   ["negative radius in (sphere (0 0 30) -2 m)"]);
+        assert_eq!(lint("(plane (0 3 0) (0 0 0) m)"),
+                   ["degenerate normal in (plane (0 3 0) (0 0 0) m)"]);
+    }
+
+    #[test]
+    fn objects_behind_the_camera() {
+        assert_eq!(lint("(sphere (0 0 -10) 5 m)"),
+                   ["object behind the camera: (sphere (0 0 -10) 5 m)"]);
+        assert_eq!(lint("(eye (0 0 50)) (sphere (0 0 30) 5 m)"),
+                   ["object behind the camera: (sphere (0 0 30) 5 m)"]);
+        // partly in front
+        assert!(lint("(sphere (0 0 -3) 5 m)").is_empty());
+        assert_eq!(lint("(group g (sphere (0 0 0) 1 m))
+                         (instance g (transform (translate 0 0 -5)))"),
+                   ["object behind the camera: \
+                     (instance g (transform (translate 0 0 -5)))"]);
+    }
+}
@@ -6,18 +6,53 @@ mod render;
 mod raytracer;
 mod expr;
 mod scene;
+mod sampler;
+mod filter;
+mod film;
+mod tile;
+mod checkpoint;
+mod hdr;
+mod tonemap;
+mod aov;
+mod denoise;
+mod post;
+mod eval;
+mod transform;
+mod writer;
+mod format;
+mod lint;
+mod json;
+#[cfg(test)]
+mod testutil;
 
+use std::io;
 use std::io::prelude::*;
+use std::fs;
 use std::fs::File;
+use std::path::Path;
 use std::time::Instant;
 use std::env;
+use std::process;
 
 use color::Color;
+use hdr::{is_hdr, write_hdr};
+use tonemap::{ToneMap, tone_map, get_tone_mapper};
 use raytracer::*;
-use render::render_frame;
+use render::{render_frame, render_film, render_aovs, resolve};
+use film::new_film;
+use aov::{Aov, AOV_NAMES, aov_pass, aov_display};
+use denoise::{Denoise, DEFAULT_DENOISE_RADIUS, denoise, get_denoise_kind};
+use post::apply_post;
 use scene::load_scene;
+use writer::write_scene;
+use format::format_scene;
+use lint::lint_scene;
+use sampler::{SamplerConfig, get_sampler_kind};
+use tile::{Tile, get_tile_order};
+use checkpoint::{Checkpoint, CheckpointConfig, hash_scene, hash_tile_range,
+                 load_checkpoint, save_checkpoint};
 
-type OutResult = Result<(), std::io::Error>;
+type OutResult = Result<(), io::Error>;
 
 fn main() {
     let args: Vec<String> = env::args().collect();    
@@ -25,35 +60,437 @@ fn main() {
         println!("needs one argument");
         return;
     }
-    let filename = "out.ppm";
-    if let Some(scene) = load_scene(args[1].as_str()) {
-        let cam = scene.camera;
+    if args[1] == "merge" {
+        if let Err(err) = merge_partials(&args[2..]) {
+            println!("Error: {}", err);
+        }
+        return;
+    }
+    if args[1] == "export" && args.len() == 4 {
+        match export_scene(&args[2], &args[3]) {
+            Ok(()) => println!("{} written.", args[3]),
+            Err(err) => println!("Error: {}", err)
+        }
+        return;
+    }
+    // both fail when a file has errors or warnings, to be run as checks
+    if args[1] == "fmt" {
+        let mut failed = false;
+        for filename in &args[2..] {
+            if let Err(err) = format_file(filename) {
+                println!("{}: {}", filename, err);
+                failed = true;
+            }
+        }
+        if failed {
+            process::exit(1);
+        }
+        return;
+    }
+    if args[1] == "lint" {
+        let mut failed = false;
+        for filename in &args[2..] {
+            match lint_scene(filename) {
+                Some(warnings) => for warning in warnings {
+                    println!("{}: {}", filename, warning);
+                    failed = true;
+                },
+                None => {
+                    println!("{}: cannot load the scene", filename);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            process::exit(1);
+        }
+        return;
+    }
+    let output = get_arg(&args[2..], "-o").unwrap_or("out.ppm");
+    match get_frames(&args[2..]) {
+        Err(err) => println!("Invalid frames: {}", err),
+        Ok(Some((start, end))) => {
+            for frame in start..end {
+                let filename = frame_filename(output, frame);
+                println!("frame {}:", frame);
+                render_file(&args, frame, filename.as_str());
+            }
+        },
+        Ok(None) => render_file(&args, 0, output)
+    }
+}
+
+// Writes the first frame of a scene back in the canonical form.
+fn export_scene(input: &str, output: &str) -> OutResult {
+    let scene = load_scene(input, 0).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "cannot load the scene")
+    })?;
+    write_scene(&scene, &mut File::create(output)?)
+}
+
+// Formats a scene file in place, leaving it as is when it cannot be read.
+fn format_file(filename: &str) -> OutResult {
+    let source = fs::read_to_string(filename)?;
+    let formatted = format_scene(&source).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "cannot parse the scene")
+    })?;
+    if formatted != source {
+        fs::write(filename, formatted)?;
+        println!("{} formatted.", filename);
+    }
+    Ok(())
+}
+
+// Inserts the frame number before the extension: out.0042.ppm
+fn frame_filename(output: &str, frame: i64) -> String {
+    tag_filename(output, &format!("{:04}", frame))
+}
+
+// Inserts `tag` before the extension of the file name, or after the name
+// without one.
+fn tag_filename(output: &str, tag: &str) -> String {
+    let path = Path::new(output);
+    match path.extension() {
+        Some(ext) => path.with_extension(format!("{}.{}", tag,
+                                                 ext.to_string_lossy()))
+            .to_string_lossy()
+            .into_owned(),
+        None => format!("{}.{}", output, tag)
+    }
+}
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let pos = args.iter().position(|a| a == name)?;
+    args.get(pos + 1).map(|a| a.as_str())
+}
+
+// Parses "--frames start..end", the end frame being excluded. None
+// without the option.
+fn get_frames(args: &[String]) -> Result<Option<(i64, i64)>, String> {
+    let Some(frames) = get_arg(args, "--frames") else {
+        return Ok(None);
+    };
+    let range = frames.split_once("..").and_then(|(start, end)| {
+        Some((start.parse::<i64>().ok()?, end.parse::<i64>().ok()?))
+    });
+    match range {
+        None => Err(format!("{} is not a range start..end", frames)),
+        Some((start, _)) if start < 0 => {
+            Err(format!("{} starts before frame 0", frames))
+        },
+        Some(range) => Ok(Some(range))
+    }
+}
+
+// Parses "--crop x0,y0,x1,y1", clipped to the frame. None without the
+// option.
+fn get_crop(args: &[String], cam: &Camera) -> Result<Option<Tile>, String> {
+    let Some(crop) = get_arg(args, "--crop") else {
+        return Ok(None);
+    };
+    let v = crop.split(',')
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<u32>>>()
+        .filter(|v| v.len() == 4)
+        .ok_or_else(|| format!("{} is not a rectangle x0,y0,x1,y1", crop))?;
+    let tile = Tile { x0: v[0], y0: v[1],
+                      x1: v[2].min(cam.width), y1: v[3].min(cam.height) };
+    if tile.x0 >= tile.x1 || tile.y0 >= tile.y1 {
+        return Err(format!("{} is empty within the frame", crop));
+    }
+    Ok(Some(tile))
+}
+
+// "--sampler name" and "--samples n" override the scene settings.
+fn get_sampler(args: &[String], config: SamplerConfig) -> SamplerConfig {
+    let kind = get_arg(args, "--sampler")
+        .and_then(get_sampler_kind)
+        .unwrap_or(config.kind);
+    let samples = get_arg(args, "--samples")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(config.samples);
+    SamplerConfig { kind, samples, ..config }
+}
+
+// Applies the options overriding the scene settings, failing on the ones
+// that cannot be read and would change the image.
+fn apply_args(args: &[String], scene: &mut Scene) -> Result<(), String> {
+    scene.sampler = get_sampler(args, scene.sampler);
+    if let Some(seed) = get_arg(args, "--seed") {
+        scene.seed = seed.parse()
+            .map_err(|err| format!("invalid seed {}: {}", seed, err))?;
+    }
+    if let Some(n) = get_arg(args, "--threads").and_then(|n| n.parse().ok()) {
+        scene.threads = n;
+    }
+    if let Some(size) = get_arg(args, "--tile-size")
+        .and_then(|n| n.parse().ok()) {
+        scene.tiles.size = size;
+    }
+    if let Some(order) = get_arg(args, "--tile-order")
+        .and_then(get_tile_order) {
+        scene.tiles.order = order;
+    }
+    if let Some(mapper) = get_arg(args, "--tonemap")
+        .and_then(get_tone_mapper) {
+        scene.tone.mapper = mapper;
+    }
+    if let Some(e) = get_arg(args, "--exposure")
+        .and_then(|e| e.parse().ok()) {
+        scene.tone.exposure = e;
+    }
+    if let Some(kind) = get_arg(args, "--denoise")
+        .and_then(get_denoise_kind) {
+        scene.denoise = Some(Denoise { kind,
+                                       radius: DEFAULT_DENOISE_RADIUS });
+    }
+    // "--denoise-radius n" applies to the scene denoiser too
+    if let Some(d) = scene.denoise.as_mut() {
+        if let Some(r) = get_arg(args, "--denoise-radius")
+            .and_then(|n| n.parse().ok()) {
+            d.radius = r;
+        }
+    }
+    if let Some(crop) = get_crop(args, &scene.camera)
+        .map_err(|err| format!("invalid crop: {}", err))? {
+        scene.crop = Some(crop);
+    }
+    if let Some((start, end)) = get_arg(args, "--tile-range")
+        .and_then(|r| r.split_once("..")) {
+        scene.tile_range = start.parse().ok().zip(end.parse().ok());
+    }
+    if let Some(samples) = get_arg(args, "--progressive")
+        .and_then(|n| n.parse().ok()) {
+        scene.progressive = Some(Progressive { samples,
+                                               time: None,
+                                               noise: None });
+    }
+    // the stop criteria only apply to progressive renders
+    if let Some(p) = scene.progressive.as_mut() {
+        if let Some(t) = get_arg(args, "--time-limit") {
+            p.time = t.parse().ok();
+        }
+        if let Some(n) = get_arg(args, "--noise-threshold") {
+            p.noise = n.parse().ok();
+        }
+    }
+    Ok(())
+}
+
+// "--checkpoint secs" periodically saves the render state next to the
+// output file, "--resume" starts over from it if it was saved for the
+// same scene.
+fn get_checkpoint(args: &[String], scene: &mut Scene, n: i64, filename: &str)
+                  -> Option<Checkpoint> {
+    let ckpt_file = format!("{}.ckpt", filename);
+    let hash = hash_tile_range(hash_scene(scene, n), scene.tile_range);
+    if let Some(interval) = get_arg(&args[2..], "--checkpoint")
+        .and_then(|t| t.parse().ok()) {
+        scene.checkpoint = Some(CheckpointConfig { filename: ckpt_file.clone(),
+                                                   interval, hash });
+    }
+    if !args.iter().any(|a| a == "--resume") {
+        return None;
+    }
+    match load_checkpoint(ckpt_file.as_str()) {
+        Ok(state) if state.fits(hash, scene.camera.width,
+                                scene.camera.height) => {
+                println!("resuming from {}.", ckpt_file);
+                Some(state)
+            },
+        Ok(_) => {
+            println!("{} was saved for another scene.", ckpt_file);
+            None
+        },
+        Err(err) => {
+            println!("unable to resume from {}: {}", ckpt_file, err);
+            None
+        }
+    }
+}
+
+fn render_file(args: &[String], n: i64, filename: &str) {
+    if let Some(mut scene) = load_scene(args[1].as_str(), n) {
+        if let Err(err) = apply_args(&args[2..], &mut scene) {
+            println!("Error: {}", err);
+            process::exit(1);
+        }
+        let resume = get_checkpoint(args, &mut scene, n, filename);
+        let (width, height) = frame_size(&scene);
+        let tone = scene.tone;
+        // the passes are written aside, the output being the final image
+        let preview_file = tag_filename(filename, "preview");
+        let mut preview = |frame: &[Color]| {
+            match write_frame(frame, width, height, &tone, &preview_file) {
+                Ok(()) => println!("{} written.", preview_file),
+                Err(err) => println!("Error: {}", err)
+            }
+        };
         println!("rendering...");
         let now = Instant::now();
-        let frame = render_frame(&scene);
+        // a partial render only saves its film, for the merge command
+        if let Some(partial) = get_arg(&args[2..], "--partial") {
+            let mut state = render_film(&scene, resume, &mut |_| {});
+            state.hash = hash_scene(&scene, n);
+            match save_checkpoint(partial, &state) {
+                Ok(()) => println!("{} written.", partial),
+                Err(err) => println!("Error: {}", err)
+            }
+            return;
+        }
+        let frame = render_frame(&scene, resume, &mut preview);
+        let names = get_aovs(&args[2..]);
+        let aovs = get_aov_buffers(&scene, &names);
+        let frame = develop(&scene, frame, &aovs, n);
         let time = now.elapsed().as_millis() as f32 / 1000.0;
         println!("done in {} seconds.", time);
-        match write_image(frame_to_image(&frame), cam, filename) {
-            Ok(()) => println!("{} written.", filename),
-            Err(err) => println!("Error: {}", err)
+        if let Err(err) = write_output(&scene, &frame, &aovs, &names,
+                                       filename) {
+            println!("Error: {}", err);
         }
     } else {
         println!("unable to load scene.");
     }
 }
 
-fn frame_to_image(frame: &Vec<Color>) -> Vec<u8> {
+// The AOVs needed by the denoiser or to be written.
+fn get_aov_buffers(scene: &Scene, names: &[&str]) -> Vec<Aov> {
+    if scene.denoise.is_some() || !names.is_empty() {
+        render_aovs(scene)
+    } else {
+        Vec::new()
+    }
+}
+
+// Denoises the frame and applies the post effects of the scene. The grain
+// changes with the frame number `n`.
+fn develop(scene: &Scene, mut frame: Vec<Color>, aovs: &[Aov], n: i64)
+           -> Vec<Color> {
+    let (width, height) = frame_size(scene);
+    if let Some(settings) = scene.denoise {
+        println!("denoising...");
+        frame = denoise(&frame, aovs, width, height, &settings);
+    }
+    let seed = scene.seed.wrapping_add(n as u64);
+    apply_post(&frame, width, height, &scene.post, seed)
+}
+
+// Writes the frame tone mapped as the scene asks, and the AOVs named.
+fn write_output(scene: &Scene, frame: &[Color], aovs: &[Aov],
+                names: &[&str], filename: &str) -> OutResult {
+    let (width, height) = frame_size(scene);
+    write_frame(frame, width, height, &scene.tone, filename)?;
+    println!("{} written.", filename);
+    write_aovs(aovs, names, width, height, filename)
+}
+
+// The size of the crop rectangle, or of the whole frame.
+fn frame_size(scene: &Scene) -> (u32, u32) {
+    scene.crop.map_or((scene.camera.width, scene.camera.height),
+                      |c| (c.width(), c.height()))
+}
+
+// "--aov depth,normal,..." or "--aov all" writes each pass next to the
+// output file: out.depth.ppm, out.normal.ppm...
+fn get_aovs(args: &[String]) -> Vec<&str> {
+    match get_arg(args, "--aov") {
+        None => Vec::new(),
+        Some("all") => AOV_NAMES.to_vec(),
+        Some(list) => list.split(',')
+            .filter(|name| {
+                let known = AOV_NAMES.contains(name);
+                if !known {
+                    println!("unknown AOV: {}", name);
+                }
+                known
+            })
+            .collect()
+    }
+}
+
+fn aov_filename(output: &str, name: &str) -> String {
+    tag_filename(output, name)
+}
+
+fn write_aovs(aovs: &[Aov], names: &[&str], width: u32, height: u32,
+              filename: &str) -> OutResult {
+    for name in names {
+        let aov_file = aov_filename(filename, name);
+        if is_hdr(filename) {
+            if let Some(pass) = aov_pass(aovs, name) {
+                write_hdr(&pass, width, height, aov_file.as_str())?;
+            }
+        } else if let Some(pass) = aov_display(aovs, name) {
+            write_image(frame_to_image(&pass), width, height,
+                        aov_file.as_str())?;
+        }
+        println!("{} written.", aov_file);
+    }
+    Ok(())
+}
+
+// "merge scene.conf out.ppm part1 part2 ... [options]" sums the films of
+// partial renders of the scene and writes the final image, denoised, with
+// the post effects and tone mapping of the scene reloaded with the same
+// options as the partials. The frame is the start of --frames, if given.
+fn merge_partials(args: &[String]) -> OutResult {
+    let (input, args) = args.split_first()
+        .ok_or_else(|| io::Error::other("needs the scene file"))?;
+    let (filename, args) = args.split_first()
+        .ok_or_else(|| io::Error::other("needs an output file"))?;
+    // the partial files come before the options
+    let count = args.iter().take_while(|a| !a.starts_with("--")).count();
+    let (parts, options) = args.split_at(count);
+    if parts.is_empty() {
+        return Err(io::Error::other("needs partial files"));
+    }
+    let frames = get_frames(options).map_err(io::Error::other)?;
+    let n = frames.map_or(0, |(start, _)| start);
+    let mut scene = load_scene(input, n)
+        .ok_or_else(|| io::Error::other("cannot load the scene"))?;
+    apply_args(options, &mut scene).map_err(io::Error::other)?;
+    let hash = hash_scene(&scene, n);
+    let cam = scene.camera;
+    let mut film = new_film(0, 0, cam.width, cam.height);
+    for part in parts {
+        let state = load_checkpoint(part)?;
+        if !state.fits(hash, film.width, film.height) {
+            return Err(io::Error::other(
+                format!("{} is from another scene", part)));
+        }
+        film.merge(&state.film);
+    }
+    let names = get_aovs(options);
+    let aovs = get_aov_buffers(&scene, &names);
+    let frame = develop(&scene, resolve(&scene, &film), &aovs, n);
+    write_output(&scene, &frame, &aovs, &names, filename)
+}
+
+// Floating point formats get the raw frame, the others get 8 bits colors
+// once tone mapped.
+fn write_frame(frame: &[Color], width: u32, height: u32, tone: &ToneMap,
+               filename: &str) -> OutResult {
+    if is_hdr(filename) {
+        write_hdr(frame, width, height, filename)
+    } else {
+        let display = tone_map(frame, tone);
+        write_image(frame_to_image(&display), width, height, filename)
+    }
+}
+
+fn frame_to_image(frame: &[Color]) -> Vec<u8> {
     let mut buffer = vec![0_u8 ; frame.len() * 3];    
     for (i, c) in frame.iter().enumerate() {
-        buffer[i*3  ] = c.0.min(255.0).max(0.0) as u8;
-        buffer[i*3+1] = c.1.min(255.0).max(0.0) as u8;
-        buffer[i*3+2] = c.2.min(255.0).max(0.0) as u8;
+        buffer[i*3  ] = (c.0 * 255.0).round().clamp(0.0, 255.0) as u8;
+        buffer[i*3+1] = (c.1 * 255.0).round().clamp(0.0, 255.0) as u8;
+        buffer[i*3+2] = (c.2 * 255.0).round().clamp(0.0, 255.0) as u8;
     }
     buffer
 }
 
-fn write_image(img: Vec<u8>, cam: Camera, filename: &str) -> OutResult {
-    let header = format!("P6 {} {} 255\n", cam.width, cam.height);    
+fn write_image(img: Vec<u8>, width: u32, height: u32, filename: &str)
+               -> OutResult {
+    let header = format!("P6 {} {} 255\n", width, height);    
     let mut file = File::create(filename)?;
     _ = file.write(header.as_bytes())?;
     _ = file.write(&img)?;
@@ -1,20 +1,21 @@
 use crate::vec3::{Vec3, Float, Dot, Norm};
-use crate::color::Material;
 use crate::raytracer::Intersect;
+use crate::expr::{Expr, new_form, new_symbol, new_vec};
 use std::option::Option;
 
 pub struct Plane {
     pos: Vec3,
     normal: Vec3,
-    mat: Box<dyn Material>
+    mat: usize
 }
 
-pub fn new_plane(p : Vec3, n : Vec3, m : Box<dyn Material>) -> Box<Plane> {
+pub fn new_plane(p : Vec3, n : Vec3, m : usize) -> Box<Plane> {
     Box::new(Plane { pos: p, normal: n, mat: m })
 }
 
 impl Intersect for Plane {
-    fn intersect(&self, orig : &Vec3, dir : &Vec3) -> Option<Float> {
+    fn intersect(&self, orig : &Vec3, dir : &Vec3, _time : Float)
+                 -> Option<Float> {
         let denom = dir.dot(&self.normal);
         let p = self.pos - *orig;
         if denom < 0.000001 {
@@ -25,16 +26,21 @@ impl Intersect for Plane {
         }
     }
 
-    fn get_normal(&self, _v : &Vec3) -> Vec3 {
+    fn get_normal(&self, _v : &Vec3, _time : Float) -> Vec3 {
         self.normal.normalized() * -1.0
     }
     
-    fn get_surface(&self, v : &Vec3) -> Vec3 {
+    fn get_surface(&self, v : &Vec3, _time : Float) -> Vec3 {
         *v + self.normal.normalized() * -0.0001
     }
 
-    fn get_material(&self) -> &dyn Material {
-        &*self.mat
+    fn get_material(&self) -> usize {
+        self.mat
+    }
+
+    fn to_expr(&self, materials: &[String]) -> Expr {
+        new_form("plane", vec![new_vec(&self.pos), new_vec(&self.normal),
+                               new_symbol(&materials[self.mat])])
     }
 
 }
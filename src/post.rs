@@ -0,0 +1,122 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::vec3::Float;
+use crate::color::{Color, new_color, luminance};
+use crate::raytracer::BLACK;
+
+// Image effects applied in order to the linear frame, before tone mapping.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PostEffect {
+    // glow of the pixels brighter than `threshold`, `radius` in pixels
+    Bloom { threshold: Float, radius: Float },
+    // darkening of the corners, 1.0 makes them black
+    Vignette { strength: Float },
+    // red and blue channels scaled from the center by -/+ `amount`
+    ChromaticAberration { amount: Float },
+    // random variation of the pixel brightness
+    Grain { amount: Float }
+}
+
+pub fn apply_post(frame: &[Color], width: u32, height: u32,
+                  effects: &[PostEffect], seed: u64) -> Vec<Color> {
+    effects.iter().fold(frame.to_vec(), |frame, effect| match *effect {
+        PostEffect::Bloom { threshold, radius } =>
+            bloom(&frame, width, height, threshold, radius),
+        PostEffect::Vignette { strength } =>
+            vignette(&frame, width, height, strength),
+        PostEffect::ChromaticAberration { amount } =>
+            aberration(&frame, width, height, amount),
+        PostEffect::Grain { amount } => grain(&frame, amount, seed)
+    })
+}
+
+fn bloom(frame: &[Color], width: u32, height: u32, threshold: Float,
+         radius: Float) -> Vec<Color> {
+    let bright : Vec<Color> = frame.iter()
+        .map(|c| if luminance(c) > threshold { *c } else { BLACK })
+        .collect();
+    let glow = blur(&bright, width, height, radius);
+    frame.iter().zip(glow.iter()).map(|(c, g)| *c + *g).collect()
+}
+
+// Separable gaussian blur, with a standard deviation of half the radius.
+fn blur(frame: &[Color], width: u32, height: u32, radius: Float)
+        -> Vec<Color> {
+    let r = radius.ceil().max(0.0) as i64;
+    let sigma = (radius / 2.0).max(0.5);
+    let kernel : Vec<Float> = (-r..=r)
+        .map(|i| (-((i * i) as Float) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total : Float = kernel.iter().sum();
+    let (w, h) = (i64::from(width), i64::from(height));
+    let pass = |src: &[Color], (dx, dy): (i64, i64)| -> Vec<Color> {
+        (0..h).flat_map(|y| (0..w).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                kernel.iter().enumerate().fold(BLACK, |sum, (k, weight)| {
+                    let o = k as i64 - r;
+                    let sx = (x + o * dx).clamp(0, w - 1);
+                    let sy = (y + o * dy).clamp(0, h - 1);
+                    sum + src[(sy * w + sx) as usize] * (weight / total)
+                })
+            })
+            .collect()
+    };
+    pass(&pass(frame, (1, 0)), (0, 1))
+}
+
+// Squared distance to the center, 1.0 in the corners.
+fn radius2(x: Float, y: Float, width: u32, height: u32) -> Float {
+    let (cx, cy) = (width as Float / 2.0, height as Float / 2.0);
+    ((x - cx).powi(2) + (y - cy).powi(2)) / (cx * cx + cy * cy)
+}
+
+fn vignette(frame: &[Color], width: u32, height: u32, strength: Float)
+            -> Vec<Color> {
+    frame.iter().enumerate()
+        .map(|(i, c)| {
+            let (x, y) = ((i as u32 % width) as Float + 0.5,
+                          (i as u32 / width) as Float + 0.5);
+            *c * (1.0 - strength * radius2(x, y, width, height)).max(0.0)
+        })
+        .collect()
+}
+
+fn aberration(frame: &[Color], width: u32, height: u32, amount: Float)
+              -> Vec<Color> {
+    let (cx, cy) = (width as Float / 2.0, height as Float / 2.0);
+    frame.iter().enumerate()
+        .map(|(i, c)| {
+            let (x, y) = ((i as u32 % width) as Float + 0.5,
+                          (i as u32 / width) as Float + 0.5);
+            let at = |scale: Float| {
+                bilinear(frame, width, height,
+                         cx + (x - cx) * scale, cy + (y - cy) * scale)
+            };
+            new_color(at(1.0 - amount).0, c.1, at(1.0 + amount).2)
+        })
+        .collect()
+}
+
+// Color at a position in pixels, pixel centers being at +0.5.
+fn bilinear(frame: &[Color], width: u32, height: u32, x: Float, y: Float)
+            -> Color {
+    let (w, h) = (i64::from(width), i64::from(height));
+    let (fx, fy) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (fx.floor() as i64, fy.floor() as i64);
+    let (tx, ty) = (fx - fx.floor(), fy - fy.floor());
+    let px = |x: i64, y: i64| {
+        frame[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize]
+    };
+    (px(x0, y0) * (1.0 - tx) + px(x0 + 1, y0) * tx) * (1.0 - ty)
+        + (px(x0, y0 + 1) * (1.0 - tx) + px(x0 + 1, y0 + 1) * tx) * ty
+}
+
+fn grain(frame: &[Color], amount: Float, seed: u64) -> Vec<Color> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    frame.iter()
+        .map(|c| {
+            let noise : Float = rng.gen::<Float>() * 2.0 - 1.0;
+            *c * (1.0 + amount * noise).max(0.0)
+        })
+        .collect()
+}
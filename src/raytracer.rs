@@ -1,37 +1,72 @@
 pub const SUBSAMPLE : u32 = 25;
 pub const REFLECTIONS : u32 = 10;
 pub const BLACK : Color = Vec3(0.0, 0.0, 0.0);
-pub const THREADS : u32 = 8;
 
 use std::option::Option;
 use crate::color::{Color, Lights};
 use crate::vec3::{ Vec3, Float };
 use crate::color::{Material};
+use crate::expr::Expr;
+use crate::sampler::SamplerConfig;
+use crate::filter::Filter;
+use crate::tile::{Tile, TileConfig};
+use crate::checkpoint::CheckpointConfig;
+use crate::tonemap::ToneMap;
+use crate::denoise::Denoise;
+use crate::post::PostEffect;
 
-#[derive(Clone,Copy,PartialEq,Eq)]
+#[derive(Clone,Copy,PartialEq)]
 pub struct Camera {
     pub width: u32,
     pub height: u32,
-    pub depth: u32
+    pub depth: u32,
+    pub eye: Vec3,
+    pub aperture: Float,
+    pub focus: Float,
+    pub blades: u32,
+    pub shutter: (Float, Float)
 }
 
-impl Camera {
-    pub fn get_frame_size(&self) -> usize {
-	(self.width * self.height) as usize
-    }
+// Renders passes of doubling sample counts until `samples` samples per
+// pixel are taken, or the time limit (in seconds) or the noise threshold
+// is reached.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Progressive {
+    pub samples: u32,
+    pub time: Option<Float>,
+    pub noise: Option<Float>
 }
 
 pub struct Scene {
     pub camera: Camera,
     pub lights: Lights,
     pub objects: Vec<Box<dyn Intersect>>,
-    pub reflections: u32
+    // shared by the objects, which refer to them by index
+    pub materials: Vec<Box<dyn Material>>,
+    pub material_names: Vec<String>,
+    pub reflections: u32,
+    pub sampler: SamplerConfig,
+    pub filter: Filter,
+    pub seed: u64,
+    pub tiles: TileConfig,
+    pub threads: u32,
+    pub progressive: Option<Progressive>,
+    pub checkpoint: Option<CheckpointConfig>,
+    pub crop: Option<Tile>,
+    pub tile_range: Option<(usize, usize)>,
+    pub tone: ToneMap,
+    pub denoise: Option<Denoise>,
+    pub post: Vec<PostEffect>
 }
 
 pub trait Intersect : Sync + Send {
-    fn intersect(&self, orig : &Vec3, dir : &Vec3) -> Option<Float>;
-    fn get_surface(&self, v : &Vec3) -> Vec3;
-    fn get_normal(&self, v : &Vec3) -> Vec3;
-    fn get_material(&self) -> &dyn Material;
+    fn intersect(&self, orig : &Vec3, dir : &Vec3, time : Float)
+                 -> Option<Float>;
+    fn get_surface(&self, v : &Vec3, time : Float) -> Vec3;
+    fn get_normal(&self, v : &Vec3, time : Float) -> Vec3;
+    // Index of the material in the scene materials.
+    fn get_material(&self) -> usize;
+    // The form describing the object, given the names of the materials.
+    fn to_expr(&self, materials: &[String]) -> Expr;
 }
 
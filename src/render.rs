@@ -1,43 +1,230 @@
-use crate::vec3::{ Vec3, Float, Dot };
+use crate::vec3::{ Vec3, Float, Dot, Norm };
 use crate::color::Color;
 use crate::raytracer::*;
+use crate::sampler::{Sampler, SamplerConfig, SamplerKind, new_sampler,
+                     sample_count};
+use crate::film::{Film, new_film};
+use crate::tile::{Tile, make_tiles};
+use crate::checkpoint::{Checkpoint, save_checkpoint};
+use crate::aov::{Aov, NO_HIT};
 
-use rand::prelude::*;
+use std::f32::consts::PI;
 
 use std::thread;
+use std::time::Instant;
+use std::collections::BTreeMap;
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-type Hit<'a> = (&'a dyn Intersect, Vec3);
-
-pub struct Line(u32, Vec<Color>);
+type Hit<'a> = (usize, &'a dyn Intersect, Vec3);
 
 #[derive(Debug,Clone,Copy)]
 struct Ray {
     orig: Vec3,
     dir: Vec3,
+    time: Float,
+}
+
+// Renders the scene, starting over from `resume` when given. The frame is
+// restricted to the crop rectangle, if any.
+pub fn render_frame(scene: &Scene, resume: Option<Checkpoint>,
+                    preview: &mut dyn FnMut(&[Color])) -> Vec<Color> {
+    let state = render_film(scene, resume, preview);
+    resolve(scene, &state.film)
+}
+
+pub fn render_film(scene: &Scene, resume: Option<Checkpoint>,
+                   preview: &mut dyn FnMut(&[Color])) -> Checkpoint {
+    let cam = scene.camera;
+    let mut state = resume.unwrap_or_else(|| Checkpoint {
+        hash: scene.checkpoint.as_ref().map_or(0, |c| c.hash),
+        pass: 0,
+        tiles: 0,
+        samples: 0,
+        film: new_film(0, 0, cam.width, cam.height)
+    });
+    match scene.progressive {
+        None => if state.pass == 0 {
+            render_pass(scene, &mut state, &scene.sampler);
+        },
+        Some(settings) =>
+            render_progressive(scene, &settings, &mut state, preview)
+    }
+    state
+}
+
+// Renders the AOV buffers with one ray through the center of each pixel,
+// at the shutter opening and through the center of the lens. They cover
+// the crop rectangle, if any.
+pub fn render_aovs(scene: &Scene) -> Vec<Aov> {
+    let cam = scene.camera;
+    let region = scene.crop.unwrap_or(Tile { x0: 0, y0: 0,
+                                             x1: cam.width, y1: cam.height });
+    let center = Vec3(-(cam.width as Float) / 2.0 + 0.5,
+                      -(cam.height as Float) / 2.0 + 0.5,
+                      cam.depth as Float);
+    let mut aovs = vec![NO_HIT ; (region.width() * region.height()) as usize];
+    let rows = region.height().div_ceil(scene.threads.max(1)).max(1);
+    thread::scope(|s| {
+        let chunks = aovs.chunks_mut((rows * region.width()) as usize);
+        for (k, chunk) in chunks.enumerate() {
+            s.spawn(move || {
+                for (i, aov) in chunk.iter_mut().enumerate() {
+                    let i = i as u32 + k as u32 * rows * region.width();
+                    let x = region.x0 + i % region.width();
+                    let y = region.y0 + i / region.width();
+                    let ray = Ray { orig: cam.eye,
+                                    dir: Vec3(x as Float, y as Float, 0.0)
+                                        + center,
+                                    time: cam.shutter.0 };
+                    render_pixel(scene, ray, scene.reflections, Some(aov));
+                }
+            });
+        }
+    });
+    aovs
+}
+
+// The frame of the film, restricted to the crop rectangle if any.
+pub fn resolve(scene: &Scene, film: &Film) -> Vec<Color> {
+    let frame = film.resolve();
+    match scene.crop {
+        None => frame,
+        Some(crop) => (crop.y0..crop.y1)
+            .flat_map(|y| {
+                let i = (y * film.width) as usize;
+                frame[i + crop.x0 as usize .. i + crop.x1 as usize].to_vec()
+            })
+            .collect()
+    }
+}
+
+// Pixels reached by the samples of a tile through the filter.
+fn filter_margin(scene: &Scene) -> u32 {
+    (scene.filter.radius - 0.5).ceil().max(0.0) as u32
 }
 
-pub fn render_frame(scene: &Scene) -> Vec<Color> {
+// The tiles to render: the ones covering the crop rectangle and the
+// pixels whose samples reach into it, or the whole frame. With a tile
+// range only that part of them.
+fn get_tiles(scene: &Scene) -> Vec<Tile> {
     let cam = scene.camera;
-    let mut frame = vec![BLACK ; cam.get_frame_size()];
+    let frame = Tile { x0: 0, y0: 0, x1: cam.width, y1: cam.height };
+    let region = scene.crop.map_or(frame, |crop| {
+        crop.expand(filter_margin(scene), cam.width, cam.height)
+    });
+    let tiles = make_tiles(&region, &scene.tiles);
+    match scene.tile_range {
+        None => tiles,
+        Some((start, end)) => {
+            let end = end.min(tiles.len());
+            tiles[start.min(end)..end].to_vec()
+        }
+    }
+}
+
+fn render_progressive(scene: &Scene, settings: &Progressive,
+                      state: &mut Checkpoint,
+                      preview: &mut dyn FnMut(&[Color])) {
+    let start = Instant::now();
+    while state.samples < settings.samples {
+        let now = Instant::now();
+        let samples = (1 << state.pass.min(31))
+            .min(settings.samples - state.samples);
+        println!("pass {}: {} samples per pixel", state.pass + 1, samples);
+        let config = SamplerConfig { samples, ..scene.sampler };
+        let taken = render_pass(scene, state, &config);
+        preview(&resolve(scene, &state.film));
+        let noise = state.film.noise();
+        println!("{} samples per pixel, noise: {}", state.samples, noise);
+        if config.kind == SamplerKind::Adaptive && taken < samples {
+            println!("all pixels converged.");
+            break;
+        }
+        if settings.noise.is_some_and(|threshold| noise <= threshold) {
+            println!("noise threshold reached.");
+            break;
+        }
+        // the next pass takes about twice as long as this one
+        let elapsed = start.elapsed().as_secs_f32();
+        let next = 2.0 * now.elapsed().as_secs_f32();
+        if settings.time.is_some_and(|limit| elapsed + next > limit) {
+            println!("time limit reached.");
+            break;
+        }
+    }
+}
+
+// Renders the tiles of the current pass that are not in the film yet, and
+// returns the most samples taken in a pixel.
+fn render_pass(scene: &Scene, state: &mut Checkpoint, config: &SamplerConfig)
+               -> u32 {
+    let tiles = get_tiles(scene);
+    let first = state.tiles as usize;
+    // the sequences keep their scrambling and go on from the samples of
+    // the previous passes, the stratified patterns are drawn anew
+    let seed = match config.kind {
+        SamplerKind::Stratified | SamplerKind::Jittered =>
+            scene.seed.wrapping_add(u64::from(state.pass)),
+        _ => scene.seed
+    };
+    let offset = state.samples;
+    // a pass resumed with all its tiles done counts as complete
+    let mut taken = if first < tiles.len() { 0 }
+                    else { sample_count(config.kind, config.samples) };
+    let queue = AtomicUsize::new(first);
     let (tx, rx) = mpsc::channel();
     thread::scope(|s| {
-	for i in 0..THREADS {
+	for i in 0..scene.threads.max(1) {
 	    let tx_clone = tx.clone();
-	    s.spawn(move || render_slice(scene, i, tx_clone));
+	    let (tiles, queue) = (&tiles, &queue);
+	    s.spawn(move || {
+                let mut sampler = new_sampler(config, seed, offset);
+                render_tiles(scene, i, tiles, queue, sampler.as_mut(),
+                             tx_clone)
+            });
 	}
     
+	// patches overlap when the filter is wider than a pixel, they are
+	// merged in tile order so that the sums do not depend on scheduling
+	let mut pending : BTreeMap<usize, Film> = BTreeMap::new();
+	let mut next = first;
 	let mut pc = 0;
-	for n in 0..cam.height {
-            let Line(y, colors) = rx.recv().unwrap();
-            frame[(y*cam.width) as usize .. (cam.width+y*cam.width) as usize]
-		.clone_from_slice(&colors);
-            pc = update_pc(pc, cam.height, n);
+	let mut saved = Instant::now();
+	for n in first..tiles.len() {
+            let (i, patch, count) = rx.recv().unwrap();
+            taken = taken.max(count);
+            pending.insert(i, patch);
+            while let Some(patch) = pending.remove(&next) {
+                state.film.merge(&patch);
+                next += 1;
+            }
+            state.tiles = next as u32;
+            if scene.checkpoint.as_ref().is_some_and(|c| {
+                saved.elapsed().as_secs_f32() >= c.interval
+            }) {
+                checkpoint(scene, state);
+                saved = Instant::now();
+            }
+            pc = update_pc(pc, tiles.len() as u32, n as u32);
 	}
     });
     println!("100%");    
-    frame
+    state.pass += 1;
+    state.tiles = 0;
+    state.samples += taken;
+    checkpoint(scene, state);
+    taken
+}
+
+fn checkpoint(scene: &Scene, state: &Checkpoint) {
+    if let Some(config) = scene.checkpoint.as_ref() {
+        match save_checkpoint(config.filename.as_str(), state) {
+            Ok(()) => println!("checkpoint {} saved.", config.filename),
+            Err(err) => println!("Error: {}", err)
+        }
+    }
 }
 
 fn update_pc(pc : u32, total : u32, n : u32) -> u32 {
@@ -48,70 +235,122 @@ fn update_pc(pc : u32, total : u32, n : u32) -> u32 {
     new_pc
 }
 
-fn render_slice(scene: &Scene, id: u32, tx: Sender<Line>) {
-    let cam = scene.camera;
+// Pulls tiles from the shared queue until it is empty.
+fn render_tiles(scene: &Scene, id: u32, tiles: &[Tile], queue: &AtomicUsize,
+                sampler: &mut dyn Sampler, tx: Sender<(usize, Film, u32)>) {
     println!("spwan thread #{}", id);
-    let mut rng = rand::thread_rng();
-    let orig = Vec3(0.0, 0.0, 0.0);
+    loop {
+        let i = queue.fetch_add(1, Ordering::Relaxed);
+        if i >= tiles.len() {
+            break;
+        }
+        let (patch, taken) = render_tile(scene, &tiles[i], sampler);
+        tx.send((i, patch, taken)).unwrap();
+    }
+}
+
+// The patch of the tile, and the most samples taken in one of its pixels.
+fn render_tile(scene: &Scene, tile: &Tile, sampler: &mut dyn Sampler)
+               -> (Film, u32) {
+    let cam = scene.camera;
     let center = Vec3(-(cam.width as Float) / 2.0,
                       -(cam.height as Float) /2.0,
                       cam.depth as Float);
-    for yy in 0..cam.height/THREADS {
-        let y = yy*THREADS+id;
-        let mut line = vec![BLACK ; cam.width as usize];
-        for x in 0..cam.width {
+    let area = tile.expand(filter_margin(scene), cam.width, cam.height);
+    let mut patch = new_film(area.x0, area.y0, area.width(), area.height());
+    let mut taken = 0;
+    for y in tile.y0..tile.y1 {
+        for x in tile.x0..tile.x1 {
             let dir = Vec3(x as Float, y as Float, 0.0) + center;
-	    let ray = Ray { orig, dir: dir + Vec3(0.5, 0.5,0.0) };
-            let mut col = render_pixel(scene, ray, scene.reflections);
-	    let mut sub = false;
-	    let mut i = 1;
-	    let col1 = col;
-	    for (x1, y1) in [ (0.0, 0.0), (1.0, 1.0),
-			       (0.0, 1.0), (1.0, 1.0) ] {
-		let ray = Ray { orig, dir: dir + Vec3(x1, y1, 0.0) };
-                let col2 = render_pixel(scene, ray, scene.reflections);
-		if col2 != col1 {
-		    sub = true;
-		}
-		col = col + col2;		
-		i += 1;
-	    }
-	    if sub {
-		for _ in i..SUBSAMPLE {
-                    let rnd = Vec3(rng.gen(), rng.gen(), 0.0);
-                    let ray = Ray { orig, dir: dir + rnd };
-                    col = col + render_pixel(scene, ray, scene.reflections);
-		    i += 1;
-		}
-	    }
-	    //let i2 = i as Float * 10.0;
-	    line[x as usize] = col / i as Float; //Vec3(i2,i2,i2);
+            sampler.start_pixel(x, y);
+            let mut n = 0;
+            while sampler.next_sample() {
+                n += 1;
+                let (dx, dy) = sampler.get_2d();
+                let ray = camera_ray(&cam, dir + Vec3(dx, dy, 0.0), sampler);
+                let col = render_pixel(scene, ray, scene.reflections, None);
+                sampler.add_sample(&col);
+                patch.add_sample(&scene.filter, (x, y), (dx, dy), col);
+            }
+            taken = taken.max(n);
         }
-        tx.send(Line(y, line)).unwrap();
     }
+    (patch, taken)
+}
+
+// Builds the primary ray through the image plane point `dir`, at a sampled
+// time within the shutter interval. With a non-zero aperture the origin
+// is jittered over the lens and the ray is bent so that it still goes
+// through the point in focus.
+fn camera_ray(cam: &Camera, dir: Vec3, sampler: &mut dyn Sampler) -> Ray {
+    let orig = cam.eye;
+    let (open, close) = cam.shutter;
+    let time = open + (close - open) * sampler.get_1d();
+    if cam.aperture <= 0.0 {
+        return Ray { orig, dir, time };
+    }
+    let focus = orig + dir * (cam.focus / dir.2);
+    let lens = orig + sample_lens(cam, sampler.get_2d());
+    Ray { orig: lens, dir: focus - lens, time }
+}
+
+// Uniform sample over the lens: a disk, or a regular polygon when the
+// diaphragm has enough blades, which gives polygonal bokeh.
+fn sample_lens(cam: &Camera, (u, v): (Float, Float)) -> Vec3 {
+    let radius = cam.aperture / 2.0;
+    if cam.blades < 3 {
+        let r = radius * u.sqrt();
+        let theta = 2.0 * PI * v;
+        return Vec3(r * theta.cos(), r * theta.sin(), 0.0);
+    }
+    // u picks the blade triangle, and is then reused inside it
+    let n = cam.blades as Float;
+    let k = (u * n).floor();
+    let (a0, a1) = (2.0 * PI * k / n, 2.0 * PI * (k + 1.0) / n);
+    let (mut u, mut v) = (u * n - k, v);
+    if u + v > 1.0 {
+        u = 1.0 - u;
+        v = 1.0 - v;
+    }
+    Vec3(a0.cos() * u + a1.cos() * v,
+         a0.sin() * u + a1.sin() * v, 0.0) * radius
 }
 
-fn render_pixel(scene: &Scene, ray: Ray, n: u32) -> Color {
+// Fills `aov` with what the ray first hits, when given.
+fn render_pixel(scene: &Scene, ray: Ray, n: u32, aov: Option<&mut Aov>)
+                -> Color {
     match cast_ray(&scene.objects, ray) {
         None => scene.lights.bg,
-        Some((obj, p)) => {
-            let surfp = obj.get_surface(&p);
-            let np = obj.get_normal(&p);
-            let ray2 = Ray { orig: surfp, dir: scene.lights.dir};
-            let m = obj.get_material();
+        Some((i, obj, p)) => {
+            let surfp = obj.get_surface(&p, ray.time);
+            let np = obj.get_normal(&p, ray.time);
+            let ray2 = Ray { orig: surfp, dir: scene.lights.dir,
+                             time: ray.time };
+            let mat = obj.get_material();
+            let m = scene.materials[mat].as_ref();
             let col0 = m.get_color(&p, &np, &scene.lights); 
-            let col = cast_ray(&scene.objects, ray2)
-                .map_or(col0, |_| col0 * scene.lights.ambiant );
+            let shadow = cast_ray(&scene.objects, ray2).is_some();
+            let col = if shadow { col0 * scene.lights.ambiant } else { col0 };
             let reflection = m.get_reflection();
             
-            if n > 0 && reflection > 0.0 {
+            let col2 = if n > 0 && reflection > 0.0 {
                 let ray3 = Ray { orig: surfp,
-                                 dir: reflect(p-ray.orig, np) };
-                let col2 = render_pixel(scene, ray3, n-1);
-                col * (1.0-reflection) + col2 * reflection
+                                 dir: reflect(p-ray.orig, np),
+                                 time: ray.time };
+                render_pixel(scene, ray3, n-1, None) * reflection
             } else {
-                col
+                BLACK
+            };
+            if let Some(aov) = aov {
+                *aov = Aov { depth: (p - ray.orig).norm(),
+                             normal: np,
+                             albedo: m.get_albedo(&p),
+                             shadow: if shadow { 1.0 } else { 0.0 },
+                             reflection: col2,
+                             object: i as u32 + 1,
+                             material: mat as u32 + 1 };
             }
+            col * (1.0-reflection) + col2
         }
     }
 }
@@ -120,18 +359,18 @@ fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - n * v.dot(&n) * 2.0
 }
 
-fn cast_ray(objs: &[Box<dyn Intersect>], ray: Ray) -> Option<Hit> {
-    objs.iter().fold(None,
-                     |res, obj|
-                     match obj.intersect(&ray.orig, &ray.dir) {
+fn cast_ray(objs: &[Box<dyn Intersect>], ray: Ray) -> Option<Hit<'_>> {
+    objs.iter().enumerate().fold(None,
+                     |res, (i, obj)|
+                     match obj.intersect(&ray.orig, &ray.dir, ray.time) {
                          None => res,
                          Some(z) => {
                              match res {
-                                 None => Some((obj.as_ref(), z)),
-                                 Some((_,i)) if z < i =>
-                                     Some((obj.as_ref(), z)),
+                                 None => Some((i, obj.as_ref(), z)),
+                                 Some((_,_,d)) if z < d =>
+                                     Some((i, obj.as_ref(), z)),
                                  _ => res
                              }
                          }
-                     }).map(| (obj, z) | (obj, ray.dir * z + ray.orig))
+                     }).map(| (i, obj, z) | (i, obj, ray.dir * z + ray.orig))
 }
@@ -0,0 +1,340 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::vec3::Float;
+use crate::color::{Color, luminance};
+use crate::raytracer::SUBSAMPLE;
+
+// Each sample is made of PAIRS 2D points: the position in the pixel, the
+// time (first half of the pair only) and the position on the lens.
+// Further requests fall back to plain random numbers.
+const PAIRS : usize = 3;
+const PRIMES : [u32 ; PAIRS * 2] = [2, 3, 5, 7, 11, 13];
+const MIN_SAMPLES : u32 = 4;
+const ONE_MINUS_EPSILON : Float = 1.0 - Float::EPSILON;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum SamplerKind {
+    Stratified,
+    Jittered,
+    Halton,
+    Sobol,
+    Adaptive
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct SamplerConfig {
+    pub kind: SamplerKind,
+    pub samples: u32,
+    pub threshold: Float
+}
+
+pub const DEFAULT_SAMPLER : SamplerConfig = SamplerConfig {
+    kind: SamplerKind::Adaptive,
+    samples: SUBSAMPLE,
+    threshold: 0.01
+};
+
+pub fn get_sampler_kind(name: &str) -> Option<SamplerKind> {
+    match name {
+        "stratified" => Some(SamplerKind::Stratified),
+        "jittered" => Some(SamplerKind::Jittered),
+        "halton" => Some(SamplerKind::Halton),
+        "sobol" => Some(SamplerKind::Sobol),
+        "adaptive" => Some(SamplerKind::Adaptive),
+        _ => None
+    }
+}
+
+pub fn sampler_kind_name(kind: SamplerKind) -> &'static str {
+    match kind {
+        SamplerKind::Stratified => "stratified",
+        SamplerKind::Jittered => "jittered",
+        SamplerKind::Halton => "halton",
+        SamplerKind::Sobol => "sobol",
+        SamplerKind::Adaptive => "adaptive"
+    }
+}
+
+pub trait Sampler {
+    fn start_pixel(&mut self, x: u32, y: u32);
+    // Starts the next sample of the pixel, false once the pixel is done.
+    fn next_sample(&mut self) -> bool;
+    fn get_2d(&mut self) -> (Float, Float);
+    fn get_1d(&mut self) -> Float {
+        self.get_2d().0
+    }
+    // Feeds back the color of the current sample.
+    fn add_sample(&mut self, _color: &Color) {}
+}
+
+// The Halton and Sobol sequences start at index `offset`, so that a pass
+// continues the sequences of the passes before it.
+pub fn new_sampler(config: &SamplerConfig, seed: u64, offset: u32)
+                   -> Box<dyn Sampler> {
+    match config.kind {
+        SamplerKind::Adaptive => Box::new(AdaptiveSampler {
+            inner: new_sequence(SamplerKind::Halton, config.samples, seed,
+                                offset),
+            min: MIN_SAMPLES.min(config.samples),
+            threshold: config.threshold,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0
+        }),
+        kind => Box::new(new_sequence(kind, config.samples, seed, offset))
+    }
+}
+
+// The number of samples per pixel actually taken for a budget: the
+// stratified patterns round it down to a square.
+pub fn sample_count(kind: SamplerKind, samples: u32) -> u32 {
+    match kind {
+        SamplerKind::Stratified | SamplerKind::Jittered => {
+            let k = ((samples as Float).sqrt() as u32).max(1);
+            k * k
+        },
+        _ => samples.max(1)
+    }
+}
+
+// Mixes the render seed and the pixel coordinates so that every pixel
+// gets its own reproducible random stream.
+pub fn pixel_seed(seed: u64, x: u32, y: u32) -> u64 {
+    splitmix(seed ^ splitmix(u64::from(y) << 32 | u64::from(x)))
+}
+
+fn splitmix(v: u64) -> u64 {
+    let mut z = v.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+struct SequenceSampler {
+    kind: SamplerKind,
+    samples: u32,
+    seed: u64,
+    offset: u32,
+    rng: StdRng,
+    points: Vec<(Float, Float)>,
+    current: u32,
+    pair: usize
+}
+
+fn new_sequence(kind: SamplerKind, samples: u32, seed: u64, offset: u32)
+                -> SequenceSampler {
+    SequenceSampler { kind,
+                      samples: sample_count(kind, samples),
+                      seed,
+                      offset,
+                      rng: StdRng::seed_from_u64(seed),
+                      points: Vec::new(),
+                      current: 0,
+                      pair: 0 }
+}
+
+impl SequenceSampler {
+    fn generate(&mut self, pair: usize) -> Vec<(Float, Float)> {
+        let (n, offset) = (self.samples, self.offset);
+        let rng = &mut self.rng;
+        let mut points : Vec<(Float, Float)> = match self.kind {
+            SamplerKind::Stratified | SamplerKind::Jittered => {
+                let k = (n as Float).sqrt() as u32;
+                let jitter = self.kind == SamplerKind::Jittered;
+                (0..n).map(|i| {
+                    let (jx, jy) = if jitter {
+                        (rng.gen(), rng.gen())
+                    } else {
+                        (0.5, 0.5)
+                    };
+                    (((i % k) as Float + jx) / k as Float,
+                     ((i / k) as Float + jy) / k as Float)
+                }).collect()
+            },
+            SamplerKind::Sobol => {
+                let (s0, s1) : (u32, u32) = (rng.gen(), rng.gen());
+                (offset..offset + n)
+                    .map(|i| (to_unit(van_der_corput(i) ^ s0),
+                              to_unit(sobol2(i) ^ s1)))
+                    .collect()
+            },
+            _ => {
+                // Cranley-Patterson rotation decorrelates the pixels
+                let (o0, o1) : (Float, Float) = (rng.gen(), rng.gen());
+                let (b0, b1) = (PRIMES[pair * 2], PRIMES[pair * 2 + 1]);
+                (offset..offset + n)
+                    .map(|i| ((radical_inverse(b0, i) + o0).fract(),
+                              (radical_inverse(b1, i) + o1).fract()))
+                    .collect()
+            }
+        };
+        // Halton dimensions are already independent, the other patterns
+        // are shuffled so that the pairs do not correlate.
+        if pair > 0 && self.kind != SamplerKind::Halton {
+            points.shuffle(rng);
+        }
+        points
+    }
+}
+
+impl Sampler for SequenceSampler {
+    fn start_pixel(&mut self, x: u32, y: u32) {
+        self.rng = StdRng::seed_from_u64(pixel_seed(self.seed, x, y));
+        self.points = (0..PAIRS).flat_map(|p| self.generate(p)).collect();
+        self.current = 0;
+    }
+
+    fn next_sample(&mut self) -> bool {
+        self.pair = 0;
+        self.current += 1;
+        self.current <= self.samples
+    }
+
+    fn get_2d(&mut self) -> (Float, Float) {
+        let i = (self.current - 1) as usize;
+        let p = if self.pair < PAIRS {
+            self.points[self.pair * self.samples as usize + i]
+        } else {
+            (self.rng.gen(), self.rng.gen())
+        };
+        self.pair += 1;
+        (p.0.min(ONE_MINUS_EPSILON), p.1.min(ONE_MINUS_EPSILON))
+    }
+}
+
+// Draws Halton samples until the standard error of the pixel luminance
+// falls below `threshold` times its mean, or the sample budget is spent.
+struct AdaptiveSampler {
+    inner: SequenceSampler,
+    min: u32,
+    threshold: Float,
+    count: u32,
+    mean: Float,
+    m2: Float
+}
+
+impl Sampler for AdaptiveSampler {
+    fn start_pixel(&mut self, x: u32, y: u32) {
+        self.inner.start_pixel(x, y);
+        self.count = 0;
+        self.mean = 0.0;
+        self.m2 = 0.0;
+    }
+
+    fn next_sample(&mut self) -> bool {
+        if self.count >= self.min.max(2) {
+            let variance = self.m2 / (self.count - 1) as Float;
+            let error = (variance / self.count as Float).sqrt();
+            if error <= self.threshold * self.mean {
+                return false;
+            }
+        }
+        self.inner.next_sample()
+    }
+
+    fn get_2d(&mut self) -> (Float, Float) {
+        self.inner.get_2d()
+    }
+
+    fn add_sample(&mut self, color: &Color) {
+        // Welford's online variance
+        let l = luminance(color);
+        self.count += 1;
+        let delta = l - self.mean;
+        self.mean += delta / self.count as Float;
+        self.m2 += delta * (l - self.mean);
+    }
+}
+
+fn to_unit(v: u32) -> Float {
+    (f64::from(v) / 4_294_967_296.0) as Float
+}
+
+fn van_der_corput(i: u32) -> u32 {
+    i.reverse_bits()
+}
+
+// Second dimension of the Sobol sequence.
+fn sobol2(mut i: u32) -> u32 {
+    let mut v : u32 = 1 << 31;
+    let mut r : u32 = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            r ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    r
+}
+
+fn radical_inverse(base: u32, mut i: u32) -> Float {
+    let inv = 1.0 / f64::from(base);
+    let mut f = inv;
+    let mut r = 0.0;
+    while i > 0 {
+        r += f64::from(i % base) * f;
+        i /= base;
+        f *= inv;
+    }
+    r as Float
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::new_color;
+
+    const KINDS : [SamplerKind ; 4] = [SamplerKind::Stratified,
+                                      SamplerKind::Jittered,
+                                      SamplerKind::Halton,
+                                      SamplerKind::Sobol];
+
+    fn in_unit(v: Float) -> bool {
+        (0.0..1.0).contains(&v)
+    }
+
+    #[test]
+    fn sequences_take_the_samples_asked_in_the_unit_square() {
+        for kind in KINDS {
+            for samples in [1, 10, 16] {
+                let config = SamplerConfig { kind, samples, threshold: 0.0 };
+                let mut sampler = new_sampler(&config, 7, 0);
+                sampler.start_pixel(3, 5);
+                let mut count = 0;
+                while sampler.next_sample() {
+                    count += 1;
+                    // past the pairs of the sequence too
+                    for _ in 0..PAIRS + 1 {
+                        let (u, v) = sampler.get_2d();
+                        assert!(in_unit(u) && in_unit(v), "{:?}", kind);
+                    }
+                    assert!(in_unit(sampler.get_1d()));
+                }
+                assert_eq!(count, sample_count(kind, samples), "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_stops_on_flat_pixels() {
+        let config = SamplerConfig { kind: SamplerKind::Adaptive,
+                                     samples: 64, threshold: 0.01 };
+        let mut sampler = new_sampler(&config, 7, 0);
+        sampler.start_pixel(0, 0);
+        let mut count = 0;
+        while sampler.next_sample() {
+            sampler.add_sample(&new_color(0.5, 0.5, 0.5));
+            count += 1;
+        }
+        assert_eq!(count, MIN_SAMPLES);
+        sampler.start_pixel(1, 0);
+        let mut count = 0;
+        while sampler.next_sample() {
+            let l = if count % 2 == 0 { 0.0 } else { 1.0 };
+            sampler.add_sample(&new_color(l, l, l));
+            count += 1;
+        }
+        assert_eq!(count, 64);
+    }
+}
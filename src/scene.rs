@@ -1,15 +1,32 @@
-use std::fs;
-use crate::expr::{parse_all, tokenize, Expr, car, cdr};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use crate::expr::{Expr, car, cdr, list_to_vec, vec_to_list, parse_expr,
+                  tokenize};
 use crate::vec3::{ Vec3, Norm, Float };
-use crate::color::{new_color, Color, Lights, Solid, Checker, Material};
-use crate::sphere::{Sphere, new_sphere};
+use crate::color::{new_color, from_srgb, Color, Lights, Solid, Checker,
+                   Material};
+use crate::sphere::{Sphere, new_sphere, new_moving_sphere};
 use crate::plane::{Plane, new_plane};
 use std::collections::HashMap;
 use crate::raytracer::*;
+use crate::sampler::{SamplerConfig, DEFAULT_SAMPLER, get_sampler_kind};
+use crate::filter::{Filter, DEFAULT_FILTER, get_filter_kind};
+use crate::tile::{TileConfig, DEFAULT_TILES, get_tile_order};
+use crate::tonemap::{DEFAULT_TONEMAP, get_tone_mapper};
+use crate::denoise::{Denoise, DEFAULT_DENOISE_RADIUS, get_denoise_kind};
+use crate::post::PostEffect;
+use crate::eval::{new_env, expand_file};
+use crate::json::read_json_scene;
+use crate::transform::{Transform, NO_TRANSFORM, new_instance, translate,
+                       scale, rotate, matrix};
 
-fn get_float(expr: &Expr) -> Option<Float> {
+// Integers are accepted too, expressions may evaluate to them.
+pub fn get_float(expr: &Expr) -> Option<Float> {
     match expr {
         Expr::Float(f) => Some(*f as Float),
+        Expr::Num(n) => Some(*n as Float),
         _ => None
     }
 }
@@ -21,7 +38,7 @@ fn get_num(expr: &Expr) -> Option<i64> {
     }
 }
 
-fn get_symbol(expr: &Expr) -> Option<String> {
+pub fn get_symbol(expr: &Expr) -> Option<String> {
     match expr {
         Expr::Symbol(s) => Some(s.clone()),
         _ => None
@@ -34,38 +51,139 @@ fn get_cam(expr : &Expr) -> Option<Camera> {
     let d = get_num(&car(&cdr(&cdr(expr))))?;
     Some(Camera { width: w as u32,
                   height: h as u32,
-                  depth: d as u32 })
+                  depth: d as u32,
+                  eye: Vec3(0.0, 0.0, 0.0),
+                  aperture: 0.0,
+                  focus: 10.0,
+                  blades: 0,
+                  shutter: (0.0, 0.0) })
 }
 
-fn get_vec(expr: &Expr) -> Option<Vec3> {
+fn get_aperture(expr: &Expr) -> Option<(Float, u32)> {
+    let a = get_float(&car(expr))?;
+    let blades = match car(&cdr(expr)) {
+        Expr::Nil => 0,
+        e => u32::try_from(get_num(&e)?).ok()?
+    };
+    if a < 0.0 {
+        println!("Parse error: aperture {} is negative", a);
+        return None;
+    }
+    Some((a, blades))
+}
+
+pub fn get_vec(expr: &Expr) -> Option<Vec3> {
     let x = get_float(&car(expr))?;
     let y = get_float(&car(&cdr(expr)))?;
     let z = get_float(&car(&cdr(&cdr(expr))))?;
-    Some(Vec3(x as f32, y as f32, z as f32))
+    Some(Vec3(x, y, z))
+}
+
+// Index of the material named `expr` in the material table, the last one
+// defined with that name.
+fn get_material_ref(expr: &Expr, names: &[String]) -> Option<usize> {
+    let mat_name = get_symbol(expr)?;
+    names.iter().rposition(|n| *n == mat_name)
 }
 
-fn get_sphere(expr: &Expr,
-              materials: &HashMap<String, Box<dyn Material>>)
+fn get_sphere(expr: &Expr, material_names: &[String])
               -> Option<Box<Sphere>> {
     let v = get_vec(&car(expr))?;
     let r = get_float(&car(&cdr(expr)))?;
-    let mat_name = get_symbol(&car(&cdr(&cdr(expr))))?;
-    let mat = materials.get(&mat_name)?;
-    let s = new_sphere(v, r, mat.clone_box());
+    let mat = get_material_ref(&car(&cdr(&cdr(expr))), material_names)?;
+    let s = match car(&cdr(&cdr(&cdr(expr)))) {
+        Expr::Nil => new_sphere(v, r, mat),
+        e => new_moving_sphere(v, get_motion(&e)?, r, mat)
+    };
     Some(s)
 }
 
-fn get_plane(expr: &Expr,
-             materials: &HashMap<String, Box<dyn Material>>)
+fn get_plane(expr: &Expr, material_names: &[String])
              -> Option<Box<Plane>> {
     let v = get_vec(&car(expr))?;
     let n = get_vec(&car(&cdr(expr)))?;
-    let mat_name = get_symbol(&car(&cdr(&cdr(expr))))?;
-    let mat = materials.get(&mat_name)?;
-    let p = new_plane(v, n, mat.clone_box());
+    let mat = get_material_ref(&car(&cdr(&cdr(expr))), material_names)?;
+    let p = new_plane(v, n, mat);
     Some(p)
 }
 
+fn get_transform(expr: &Expr) -> Option<Transform> {
+    let op = get_symbol(&car(expr))?;
+    let arg = car(&cdr(expr));
+    let t = match op.as_str() {
+        "translate" => translate(get_vec(&cdr(expr))?),
+        "scale" => match get_float(&arg) {
+            Some(s) => scale(Vec3(s, s, s))?,
+            None => scale(get_vec(&cdr(expr))?)?
+        },
+        "rotate-x" => rotate(0, get_float(&arg)?),
+        "rotate-y" => rotate(1, get_float(&arg)?),
+        "rotate-z" => rotate(2, get_float(&arg)?),
+        "matrix" => {
+            let v = list_to_vec(&cdr(expr)).iter()
+                .map(get_float)
+                .collect::<Option<Vec<Float>>>()?;
+            if v.len() != 12 {
+                return None;
+            }
+            let row = |i: usize| [v[i * 4], v[i * 4 + 1], v[i * 4 + 2],
+                                  v[i * 4 + 3]];
+            matrix([row(0), row(1), row(2)])?
+        },
+        _ => return None
+    };
+    Some(t)
+}
+
+// (transform op...) with translate, scale, rotate-x/y/z and matrix
+// operations, applied to the objects in the order given.
+pub fn get_transforms(expr: &Expr) -> Option<Transform> {
+    is_symbol(&car(expr), "transform")?;
+    list_to_vec(&cdr(expr)).iter()
+        .try_fold(NO_TRANSFORM, |t, op| Some(t.then(&get_transform(op)?)))
+}
+
+type Group = Vec<Arc<dyn Intersect>>;
+
+// (instance name [(transform ...)]) places the objects of a group.
+fn get_instance(expr: &Expr, groups: &HashMap<String, Group>)
+                -> Option<Vec<Box<dyn Intersect>>> {
+    let group = groups.get(&get_symbol(&car(expr))?)?;
+    let transform = match car(&cdr(expr)) {
+        Expr::Nil => NO_TRANSFORM,
+        e => get_transforms(&e)?
+    };
+    let objects = group.iter()
+        .map(|obj| {
+            new_instance(obj.clone(), transform) as Box<dyn Intersect>
+        })
+        .collect();
+    Some(objects)
+}
+
+// The positional arguments of an object form.
+pub fn get_object_args(form: &str, args: &Expr) -> Option<Expr> {
+    match form {
+        "sphere" => get_keywords(form, args, &SPHERE_KEYWORDS),
+        "plane" => get_keywords(form, args, &PLANE_KEYWORDS),
+        _ => Some(args.clone())
+    }
+}
+
+// The objects made by a sphere, plane or instance form.
+fn get_objects(form: &str, args: &Expr, material_names: &[String],
+               groups: &HashMap<String, Group>)
+               -> Option<Vec<Box<dyn Intersect>>> {
+    let args = &get_object_args(form, args)?;
+    let obj : Box<dyn Intersect> = match form {
+        "sphere" => get_sphere(args, material_names)?,
+        "plane" => get_plane(args, material_names)?,
+        "instance" => return get_instance(args, groups),
+        _ => return None
+    };
+    Some(vec![obj])
+}
+
 fn is_symbol(expr: &Expr, label: &str) -> Option<()> {
     let s = get_symbol(expr)?;
     if s.as_str() == label {
@@ -77,9 +195,92 @@ fn is_symbol(expr: &Expr, label: &str) -> Option<()> {
 
 fn get_color(expr: &Expr) -> Option<Color> {
     is_symbol(&car(expr), "color")?;
+    let c = match car(&cdr(expr)) {
+        e @ Expr::Cons(_, _) => get_vec(&e),
+        _ => get_vec(&cdr(expr))
+    }?;
+    Some(from_srgb(c))
+}
+
+fn get_motion(expr: &Expr) -> Option<Vec3> {
+    is_symbol(&car(expr), "motion")?;
     get_vec(&cdr(expr))
 }
 
+fn get_shutter(expr: &Expr) -> Option<(Float, Float)> {
+    let open = get_float(&car(expr))?;
+    let close = get_float(&car(&cdr(expr)))?;
+    Some((open, close))
+}
+
+fn get_sampler(expr: &Expr) -> Option<SamplerConfig> {
+    let kind = get_sampler_kind(get_symbol(&car(expr))?.as_str())?;
+    let samples = get_num(&car(&cdr(expr)))?;
+    let threshold = match car(&cdr(&cdr(expr))) {
+        Expr::Nil => DEFAULT_SAMPLER.threshold,
+        e => get_float(&e)?
+    };
+    Some(SamplerConfig { kind, samples: samples as u32, threshold })
+}
+
+fn get_filter(expr: &Expr) -> Option<Filter> {
+    let kind = get_filter_kind(get_symbol(&car(expr))?.as_str())?;
+    let radius = get_float(&car(&cdr(expr)))?;
+    if radius <= 0.0 {
+        println!("Parse error: filter radius {} is not positive", radius);
+        return None;
+    }
+    Some(Filter { kind, radius })
+}
+
+fn get_tiles(expr: &Expr) -> Option<TileConfig> {
+    let size = get_num(&car(expr))?;
+    let order = match car(&cdr(expr)) {
+        Expr::Nil => DEFAULT_TILES.order,
+        e => get_tile_order(get_symbol(&e)?.as_str())?
+    };
+    Some(TileConfig { size: size as u32, order })
+}
+
+fn get_progressive(expr: &Expr) -> Option<Progressive> {
+    let samples = get_num(&car(expr))?;
+    let time = match car(&cdr(expr)) {
+        Expr::Nil => None,
+        e => Some(get_float(&e)?)
+    };
+    let noise = match car(&cdr(&cdr(expr))) {
+        Expr::Nil => None,
+        e => Some(get_float(&e)?)
+    };
+    Some(Progressive { samples: samples as u32, time, noise })
+}
+
+fn get_denoise(expr: &Expr) -> Option<Denoise> {
+    let kind = get_denoise_kind(get_symbol(&car(expr))?.as_str())?;
+    let radius = match car(&cdr(expr)) {
+        Expr::Nil => i64::from(DEFAULT_DENOISE_RADIUS),
+        e => get_num(&e)?
+    };
+    Some(Denoise { kind, radius: radius as u32 })
+}
+
+fn get_effect(expr: &Expr) -> Option<PostEffect> {
+    let name = get_symbol(&car(expr))?;
+    let a = get_float(&car(&cdr(expr)))?;
+    let effect = match name.as_str() {
+        "bloom" => PostEffect::Bloom {
+            threshold: a,
+            radius: get_float(&car(&cdr(&cdr(expr))))?
+        },
+        "vignette" => PostEffect::Vignette { strength: a },
+        "chromatic-aberration" =>
+            PostEffect::ChromaticAberration { amount: a },
+        "grain" => PostEffect::Grain { amount: a },
+        _ => return None
+    };
+    Some(effect)
+}
+
 fn get_specular(expr: &Expr) -> Option<(Float,Float)> {
     is_symbol(&car(expr), "spec")?;
     let y = get_float(&car(&cdr(expr)))?;
@@ -117,64 +318,337 @@ fn get_checkboard(expr: &Expr) -> Option<Box<dyn Material>> {
                     uv: uv as i32, specular: spec, reflection: refl }))
 }
 
-fn get_material(expr: &Expr) -> Option<(String, Box<dyn Material>)> {
+// A keyword argument: its name, the form its value is wrapped into to
+// give the positional argument, if any, and the default positional
+// argument, None when the keyword is required.
+type Keyword = (&'static str, Option<&'static str>, Option<&'static str>);
+
+const SPHERE_KEYWORDS : [Keyword ; 4] = [
+    (":center", None, Some("(0.0 0.0 0.0)")),
+    (":radius", None, Some("1.0")),
+    (":material", None, None),
+    (":motion", Some("motion"), Some("()"))
+];
+
+const PLANE_KEYWORDS : [Keyword ; 3] = [
+    (":point", None, Some("(0.0 0.0 0.0)")),
+    (":normal", None, Some("(0.0 1.0 0.0)")),
+    (":material", None, None)
+];
+
+const SOLID_KEYWORDS : [Keyword ; 3] = [
+    (":color", Some("color"), Some("(color 255 255 255)")),
+    (":spec", Some("spec"), Some("(spec 1.0 0.0)")),
+    (":reflection", Some("reflection"), Some("(reflection 0.0)"))
+];
+
+const CHECKER_KEYWORDS : [Keyword ; 5] = [
+    (":color1", Some("color"), Some("(color 255 255 255)")),
+    (":color2", Some("color"), Some("(color 0 0 0)")),
+    (":scale", Some("uv"), Some("(uv 10)")),
+    (":spec", Some("spec"), Some("(spec 1.0 0.0)")),
+    (":reflection", Some("reflection"), Some("(reflection 0.0)"))
+];
+
+fn is_keyword(expr: &Expr) -> bool {
+    matches!(expr, Expr::Symbol(s) if s.starts_with(':'))
+}
+
+fn wrap(tag: &str, value: Expr) -> Expr {
+    let tag = Expr::Symbol(tag.to_string());
+    match value {
+        Expr::Cons(ref head, _) if **head == tag => value,
+        Expr::Cons(_, _) => Expr::Cons(Box::new(tag), Box::new(value)),
+        _ => vec_to_list(vec![tag, value])
+    }
+}
+
+// Turns `arg... :keyword value...` into the positional arguments of the
+// form, in any order and with defaults for the omitted keywords. The
+// leading arguments fill the first positions, the trailing ones left out
+// getting their defaults too.
+fn get_keywords(form: &str, args: &Expr, keywords: &[Keyword])
+                -> Option<Expr> {
+    let args = list_to_vec(args);
+    let start = args.iter().position(is_keyword).unwrap_or(args.len());
+    if start > keywords.len() {
+        println!("Parse error: too many arguments in {}", form);
+        return None;
+    }
+    let mut values : Vec<Option<Expr>> = vec![None ; keywords.len()];
+    for (i, arg) in args[..start].iter().enumerate() {
+        values[i] = Some(arg.clone());
+    }
+    for pair in args[start..].chunks(2) {
+        let name = get_symbol(&pair[0]).filter(|_| is_keyword(&pair[0]));
+        let i = name.as_ref().and_then(|name| {
+            keywords.iter().position(|k| k.0 == name.as_str())
+        });
+        match (i, pair.get(1)) {
+            (Some(i), Some(value)) => {
+                values[i] = Some(match keywords[i].1 {
+                    Some(tag) => wrap(tag, value.clone()),
+                    None => value.clone()
+                });
+            },
+            _ => {
+                println!("Parse error: unexpected {} in {}", pair[0], form);
+                return None;
+            }
+        }
+    }
+    let values = values.into_iter().zip(keywords.iter())
+        .map(|(value, (name, _, default))| {
+            let value = value.or_else(|| {
+                let (e, _) = parse_expr(&tokenize((*default)?)?)?;
+                Some(e)
+            });
+            if value.is_none() {
+                println!("Parse error: missing {} in {}", name, form);
+            }
+            value
+        })
+        .collect::<Option<Vec<Expr>>>()?;
+    Some(vec_to_list(values))
+}
+
+// (mat name shader arg...), the arguments being positional or keywords.
+pub fn get_material(expr: &Expr)
+                    -> Option<(String, Box<dyn Material>)> {
     let name = get_symbol(&car(expr))?;
     let shader = get_symbol(&car(&cdr(expr)))?;
+    let args = cdr(&cdr(expr));
     let m : Option<Box<dyn Material>> = match shader.as_str() {
-        "solid" => get_solid(&cdr(&cdr(expr))),
-        "checkboard" => get_checkboard(&cdr(&cdr(expr))),
-        _ => None
+        "solid" => get_solid(&get_keywords("solid", &args,
+                                           &SOLID_KEYWORDS)?),
+        "checkboard" | "checker" =>
+            get_checkboard(&get_keywords("checker", &args,
+                                         &CHECKER_KEYWORDS)?),
+        _ => {
+            println!("Parse error: unknown shader {} for {}", shader, name);
+            return None;
+        }
     };
     m.map(|x| (name, x))
 }
 
-pub fn load_scene(filename: &str) -> Option<Scene> {
-    let contents = fs::read_to_string(filename)
-        .expect("Something went wrong reading the file");
-    let tokens = tokenize(contents.as_str());
-    let exprs = parse_all(&tokens).unwrap();
+// The names of the forms read by the loader, which the variables do not
+// replace at the head of a list.
+pub const FORMS : [&str; 36] = [
+    "camera", "sphere", "plane", "instance", "group", "mat", "aperture",
+    "focus-distance", "eye", "light", "shutter", "sampler", "filter",
+    "tiles", "progressive", "tonemap", "exposure", "denoise", "post",
+    "reflections", "transform", "translate", "scale", "rotate-x",
+    "rotate-y", "rotate-z", "matrix", "color", "motion", "spec",
+    "reflection", "uv", "bloom", "vignette", "chromatic-aberration",
+    "grain"
+];
+
+// The forms of a scene file, expanded for `frame`. The .json files are
+// read as JSON scenes.
+pub fn read_forms(filename: &str, frame: i64) -> Option<Vec<Expr>> {
+    let path = Path::new(filename);
+    if path.extension().is_some_and(|ext| ext == "json") {
+        return read_json_scene(path);
+    }
+    let mut forms = Vec::new();
+    expand_file(path, &mut new_env(frame), &mut forms)?;
+    Some(forms)
+}
+
+pub fn load_scene(filename: &str, frame: i64) -> Option<Scene> {
     let mut cam : Option<Camera> = None;
     let mut objects : Vec<Box<dyn Intersect>> = Vec::new();
-    let mut materials : HashMap<String, Box<dyn Material>> =
-        HashMap::new();
+    let mut materials : Vec<Box<dyn Material>> = Vec::new();
+    let mut material_names : Vec<String> = Vec::new();
+    let mut groups : HashMap<String, Group> = HashMap::new();
     let mut reflections : i64 = i64::from(REFLECTIONS);
+    let mut aperture : Option<(Float, u32)> = None;
+    let mut focus : Option<Float> = None;
+    let mut shutter : Option<(Float, Float)> = None;
+    let mut eye : Option<Vec3> = None;
+    let mut light : Vec3 = Vec3(-0.5, -1.0, -0.75);
+    let mut sampler = DEFAULT_SAMPLER;
+    let mut filter = DEFAULT_FILTER;
+    let mut tiles = DEFAULT_TILES;
+    let mut progressive : Option<Progressive> = None;
+    let mut tone = DEFAULT_TONEMAP;
+    let mut denoise : Option<Denoise> = None;
+    let mut post : Vec<PostEffect> = Vec::new();
     
-    for expr in exprs {
-        if let Expr::Cons(e_car, e_cdr) = expr {
-            if let Expr::Symbol(car_symb) = *e_car {
-                match car_symb.as_str() {
-                    "camera" => { cam = get_cam(&e_cdr) },
-                    "sphere" => {
-                        let s = get_sphere(&e_cdr, &materials)?;
-                        objects.push(s);
-                    },
-                    "plane" => {
-                        let p = get_plane(&e_cdr, &materials)?;
-                        objects.push(p);
-                    },
-                    "mat" => {
-                        let (name, mat) = get_material(&e_cdr)?;
-                        materials.insert(name, mat);
-                    },
-                    "reflections" => {
-                        reflections = get_num(&car(&e_cdr))?;
-                        println!("reflections: {:?}", reflections);
-                    },
-                    unparsed => {
-                        println!("Parse error: {:?}", unparsed);
-                        return None;
+    let forms = read_forms(filename, frame)?;
+
+    for expr in &forms {
+        // the loaders print why a form is invalid, if they can tell
+        let loaded = (|| -> Option<()> {
+            if let Expr::Cons(e_car, e_cdr) = expr.clone() {
+                if let Expr::Symbol(car_symb) = *e_car {
+                    match car_symb.as_str() {
+                        "camera" => { cam = get_cam(&e_cdr) },
+                        "sphere" | "plane" | "instance" => {
+                            objects.extend(get_objects(&car_symb, &e_cdr,
+                                                       &material_names,
+                                                       &groups)?);
+                        },
+                        "group" => {
+                            // the objects are shared by all the instances
+                            let name = get_symbol(&car(&e_cdr))?;
+                            let mut group : Group = Vec::new();
+                            for form in list_to_vec(&cdr(&e_cdr)) {
+                                let form_name = get_symbol(&car(&form))?;
+                                let objs = get_objects(&form_name, &cdr(&form),
+                                                       &material_names,
+                                                       &groups)?;
+                                group.extend(objs.into_iter().map(Arc::from));
+                            }
+                            groups.insert(name, group);
+                        },
+                        "mat" => {
+                            // a material defined again shadows the previous one
+                            // for the objects that follow, the objects before
+                            // keeping theirs
+                            let (name, mat) = get_material(&e_cdr)?;
+                            material_names.push(name);
+                            materials.push(mat);
+                        },
+                        "aperture" => {
+                            aperture = Some(get_aperture(&e_cdr)?);
+                        },
+                        "focus-distance" => {
+                            focus = Some(get_float(&car(&e_cdr))?);
+                        },
+                        "eye" => {
+                            eye = Some(get_vec(&car(&e_cdr))?);
+                        },
+                        "light" => {
+                            light = get_vec(&car(&e_cdr))?;
+                        },
+                        "shutter" => {
+                            shutter = Some(get_shutter(&e_cdr)?);
+                        },
+                        "sampler" => {
+                            sampler = get_sampler(&e_cdr)?;
+                        },
+                        "filter" => {
+                            filter = get_filter(&e_cdr)?;
+                        },
+                        "tiles" => {
+                            tiles = get_tiles(&e_cdr)?;
+                        },
+                        "progressive" => {
+                            progressive = Some(get_progressive(&e_cdr)?);
+                        },
+                        "tonemap" => {
+                            let name = get_symbol(&car(&e_cdr))?;
+                            tone.mapper = get_tone_mapper(name.as_str())?;
+                        },
+                        "exposure" => {
+                            tone.exposure = get_float(&car(&e_cdr))?;
+                        },
+                        "denoise" => {
+                            denoise = Some(get_denoise(&e_cdr)?);
+                        },
+                        "post" => {
+                            post = list_to_vec(&e_cdr).iter()
+                                .map(get_effect)
+                                .collect::<Option<Vec<PostEffect>>>()?;
+                        },
+                        "reflections" => {
+                            reflections = get_num(&car(&e_cdr))?;
+                            println!("reflections: {:?}", reflections);
+                        },
+                        unparsed => {
+                            println!("Parse error: {:?}", unparsed);
+                            return None;
+                        }
                     }
                 }
             }
+            Some(())
+        })();
+        if loaded.is_none() {
+            println!("unable to load {}", expr);
+            return None;
         }
     }
 
+    let mut camera = cam?;
+    if let Some((a, blades)) = aperture {
+        camera.aperture = a;
+        camera.blades = blades;
+    }
+    if let Some(f) = focus {
+        camera.focus = f;
+    }
+    if let Some(sh) = shutter {
+        camera.shutter = sh;
+    }
+    if let Some(e) = eye {
+        camera.eye = e;
+    }
+
     Some(Scene {
-        camera: cam?,
-        lights: Lights { dir: (Vec3(-0.5, -1.0, -0.75)).normalized(),
+        camera,
+        lights: Lights { dir: light.normalized(),
                          ambiant: 0.2,
-                         bg: new_color(20.0, 20.0, 30.0) },
+                         bg: from_srgb(new_color(20.0, 20.0, 30.0)) },
         objects,
-        reflections: reflections as u32
+        materials,
+        material_names,
+        reflections: reflections as u32,
+        sampler,
+        filter,
+        seed: 0,
+        tiles,
+        threads: thread::available_parallelism()
+            .map_or(1, |n| n.get() as u32),
+        progressive,
+        checkpoint: None,
+        crop: None,
+        tile_range: None,
+        tone,
+        denoise,
+        post
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::parse_all;
+
+    fn keywords(args: &str, keywords: &[Keyword]) -> Option<String> {
+        let args = vec_to_list(parse_all(&tokenize(args)?)?);
+        get_keywords("test", &args, keywords).map(|e| e.to_string())
+    }
+
+    #[test]
+    fn keywords_in_any_order() {
+        assert_eq!(keywords(":radius 2.0 :material m :center (1 2 3)",
+                            &SPHERE_KEYWORDS).unwrap(),
+                   "((1 2 3) 2.0 m ())");
+        assert_eq!(keywords("(1 2 3) :material m :radius 2.0",
+                            &SPHERE_KEYWORDS).unwrap(),
+                   "((1 2 3) 2.0 m ())");
+        assert_eq!(keywords(":motion (0 1 0) :material m",
+                            &SPHERE_KEYWORDS).unwrap(),
+                   "((0.0 0.0 0.0) 1.0 m (motion 0 1 0))");
+    }
+
+    #[test]
+    fn keywords_get_their_defaults() {
+        assert_eq!(keywords("", &SOLID_KEYWORDS).unwrap(),
+                   "((color 255 255 255) (spec 1.0 0.0) (reflection 0.0))");
+        assert_eq!(keywords("(color 1 2 3)", &SOLID_KEYWORDS).unwrap(),
+                   "((color 1 2 3) (spec 1.0 0.0) (reflection 0.0))");
+        assert_eq!(keywords("(1 2 3)", &SPHERE_KEYWORDS), None);
+    }
+
+    #[test]
+    fn keywords_errors() {
+        assert_eq!(keywords("(1 2 3) 2.0 m (motion 0 1 0) 5",
+                            &SPHERE_KEYWORDS), None);
+        assert_eq!(keywords(":material m :size 2", &SPHERE_KEYWORDS), None);
+        assert_eq!(keywords(":material", &SPHERE_KEYWORDS), None);
+    }
+}
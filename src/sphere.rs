@@ -1,17 +1,31 @@
 use crate::vec3::{Vec3, Float, Dot, Norm};
-use crate::color::Material;
 use crate::raytracer::Intersect;
+use crate::expr::{Expr, new_form, new_symbol, new_float, new_vec};
 use std::option::Option;
 
 pub struct Sphere {
     pos : Vec3,
     radius : Float,
     r2 : Float,
-    mat : Box<dyn Material>
+    motion : Vec3,
+    mat : usize
 }
 
-pub fn new_sphere(p : Vec3, r : Float, m : Box<dyn Material>) -> Box<Sphere> {
-    Box::new(Sphere { pos:p, radius:r, r2: r.powf(2.0), mat:m })
+pub fn new_sphere(p : Vec3, r : Float, m : usize) -> Box<Sphere> {
+    new_moving_sphere(p, p, r, m)
+}
+
+// The sphere moves linearly from p0 at time 0.0 to p1 at time 1.0.
+pub fn new_moving_sphere(p0 : Vec3, p1 : Vec3, r : Float,
+                         m : usize) -> Box<Sphere> {
+    Box::new(Sphere { pos:p0, radius:r, r2: r.powf(2.0),
+                      motion: p1 - p0, mat:m })
+}
+
+impl Sphere {
+    fn center(&self, time : Float) -> Vec3 {
+        self.pos + self.motion * time
+    }
 }
     
 enum Solution {
@@ -38,8 +52,9 @@ fn solve_quadratic(a : Float, b : Float, c : Float) -> Solution {
 }
 
 impl Intersect for Sphere {
-    fn intersect(&self, orig : &Vec3, dir : &Vec3) -> Option<Float> {
-        let l = *orig - self.pos;
+    fn intersect(&self, orig : &Vec3, dir : &Vec3, time : Float)
+                 -> Option<Float> {
+        let l = *orig - self.center(time);
         let a = dir.dot(dir);
         let b = 2.0 * l.dot(dir);
         let c = l.dot(&l) - self.r2;
@@ -53,17 +68,30 @@ impl Intersect for Sphere {
         }
     }
 
-    fn get_surface(&self, v : &Vec3) -> Vec3 {
-        let n = (*v - self.pos).normalized();
-        self.pos + n * self.radius * 1.0001
+    fn get_surface(&self, v : &Vec3, time : Float) -> Vec3 {
+        let pos = self.center(time);
+        let n = (*v - pos).normalized();
+        pos + n * self.radius * 1.0001
     }
 
-    fn get_normal(&self, v : &Vec3) -> Vec3 {
-        (*v - self.pos).normalized()
+    fn get_normal(&self, v : &Vec3, time : Float) -> Vec3 {
+        (*v - self.center(time)).normalized()
     }
     
-    fn get_material(&self) -> &dyn Material {
-        &*self.mat
+    fn get_material(&self) -> usize {
+        self.mat
+    }
+
+    fn to_expr(&self, materials: &[String]) -> Expr {
+        let mut args = vec![new_vec(&self.pos), new_float(self.radius),
+                            new_symbol(&materials[self.mat])];
+        if self.motion != Vec3(0.0, 0.0, 0.0) {
+            let end = self.pos + self.motion;
+            args.push(new_form("motion",
+                               vec![new_float(end.0), new_float(end.1),
+                                    new_float(end.2)]));
+        }
+        new_form("sphere", args)
     }
 }
 
@@ -0,0 +1,40 @@
+// Helpers shared by the tests.
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::raytracer::Scene;
+use crate::writer::write_scene;
+
+static COUNT : AtomicUsize = AtomicUsize::new(0);
+
+// A path in the temporary directory, unique to the test, whose file is
+// removed when dropped, even by a failing test.
+pub struct TempFile {
+    pub path: PathBuf
+}
+
+pub fn temp_file(ext: &str) -> TempFile {
+    let n = COUNT.fetch_add(1, Ordering::SeqCst);
+    let name = format!("raytracer-test-{}-{}.{}", process::id(), n, ext);
+    TempFile { path: std::env::temp_dir().join(name) }
+}
+
+impl TempFile {
+    pub fn name(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).unwrap_or_default();
+    }
+}
+
+// The scene written in the canonical form.
+pub fn written(scene: &Scene) -> String {
+    let mut out = Vec::new();
+    write_scene(scene, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
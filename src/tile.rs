@@ -0,0 +1,137 @@
+// Splits the frame into tiles that the render threads pull from a shared
+// queue, in an order chosen to give useful early feedback or good locality.
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    // The tile grown by `margin` pixels on each side, within the frame.
+    pub fn expand(&self, margin: u32, width: u32, height: u32) -> Tile {
+        Tile { x0: self.x0.saturating_sub(margin),
+               y0: self.y0.saturating_sub(margin),
+               x1: (self.x1 + margin).min(width),
+               y1: (self.y1 + margin).min(height) }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct TileConfig {
+    pub size: u32,
+    pub order: TileOrder
+}
+
+pub const DEFAULT_TILES : TileConfig = TileConfig {
+    size: 32,
+    order: TileOrder::Spiral
+};
+
+pub fn get_tile_order(name: &str) -> Option<TileOrder> {
+    match name {
+        "scanline" => Some(TileOrder::Scanline),
+        "spiral" => Some(TileOrder::Spiral),
+        "hilbert" => Some(TileOrder::Hilbert),
+        _ => None
+    }
+}
+
+pub fn tile_order_name(order: TileOrder) -> &'static str {
+    match order {
+        TileOrder::Scanline => "scanline",
+        TileOrder::Spiral => "spiral",
+        TileOrder::Hilbert => "hilbert"
+    }
+}
+
+// Covers `region` with tiles, in the configured order.
+pub fn make_tiles(region: &Tile, config: &TileConfig) -> Vec<Tile> {
+    let size = config.size.max(1);
+    let (nx, ny) = (region.width().div_ceil(size),
+                    region.height().div_ceil(size));
+    let cells = match config.order {
+        TileOrder::Scanline =>
+            (0..ny).flat_map(|ty| (0..nx).map(move |tx| (tx, ty))).collect(),
+        TileOrder::Spiral => spiral(nx, ny),
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            let mut cells : Vec<(u32, u32)> = (0..ny)
+                .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+                .collect();
+            cells.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+            cells
+        }
+    };
+    cells.into_iter()
+        .map(|(tx, ty)| {
+            let (x0, y0) = (region.x0 + tx * size, region.y0 + ty * size);
+            Tile { x0, y0,
+                   x1: (x0 + size).min(region.x1),
+                   y1: (y0 + size).min(region.y1) }
+        })
+        .collect()
+}
+
+// Walks outward from the center tile, skipping the steps that fall
+// outside of the grid.
+fn spiral(nx: u32, ny: u32) -> Vec<(u32, u32)> {
+    let total = (nx * ny) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut x, mut y) = ((nx as i64 - 1) / 2, (ny as i64 - 1) / 2);
+    let dirs = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut step = 1;
+    let mut d = 0;
+    while cells.len() < total {
+        for _ in 0..2 {
+            let (dx, dy) = dirs[d % 4];
+            for _ in 0..step {
+                if x >= 0 && y >= 0 && x < nx as i64 && y < ny as i64 {
+                    cells.push((x as u32, y as u32));
+                }
+                x += dx;
+                y += dy;
+            }
+            d += 1;
+        }
+        step += 1;
+    }
+    cells
+}
+
+// Distance along the Hilbert curve filling a n x n grid, n a power of two.
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d : u64 = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
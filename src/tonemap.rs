@@ -0,0 +1,85 @@
+use crate::vec3::Float;
+use crate::color::{Color, new_color, luminance};
+
+// Post stage turning the linear frame into display values in 0..1.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ToneMapper {
+    Clamp,
+    Reinhard,
+    Aces
+}
+
+// `exposure` is in stops.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct ToneMap {
+    pub mapper: ToneMapper,
+    pub exposure: Float
+}
+
+pub const DEFAULT_TONEMAP : ToneMap = ToneMap {
+    mapper: ToneMapper::Clamp,
+    exposure: 0.0
+};
+
+pub fn get_tone_mapper(name: &str) -> Option<ToneMapper> {
+    match name {
+        "clamp" => Some(ToneMapper::Clamp),
+        "reinhard" => Some(ToneMapper::Reinhard),
+        "aces" => Some(ToneMapper::Aces),
+        _ => None
+    }
+}
+
+pub fn tone_mapper_name(mapper: ToneMapper) -> &'static str {
+    match mapper {
+        ToneMapper::Clamp => "clamp",
+        ToneMapper::Reinhard => "reinhard",
+        ToneMapper::Aces => "aces"
+    }
+}
+
+pub fn tone_map(frame: &[Color], settings: &ToneMap) -> Vec<Color> {
+    let scale = settings.exposure.exp2();
+    frame.iter()
+        .map(|c| {
+            let c = map_color(*c * scale, settings.mapper);
+            new_color(linear_to_srgb(c.0), linear_to_srgb(c.1),
+                      linear_to_srgb(c.2))
+        })
+        .collect()
+}
+
+fn map_color(c: Color, mapper: ToneMapper) -> Color {
+    match mapper {
+        ToneMapper::Clamp => c,
+        ToneMapper::Reinhard => c / (1.0 + luminance(&c).max(0.0)),
+        ToneMapper::Aces => new_color(aces(c.0), aces(c.1), aces(c.2))
+    }
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: Float) -> Float {
+    let x = x.max(0.0);
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn linear_to_srgb(x: Float) -> Float {
+    encode_srgb(x.clamp(0.0, 1.0))
+}
+
+// The sRGB transfer function, without clamping.
+pub fn encode_srgb(x: Float) -> Float {
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(x: Float) -> Float {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::Arc;
+use crate::vec3::{Vec3, Float, Norm};
+use crate::raytracer::Intersect;
+use crate::expr::{Expr, new_form, new_float};
+
+// Affine transform as the 3 first rows of a 4x4 matrix, kept along with
+// its inverse.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Transform {
+    m: [[Float ; 4] ; 3],
+    inv: [[Float ; 4] ; 3]
+}
+
+const IDENTITY : [[Float ; 4] ; 3] = [[1.0, 0.0, 0.0, 0.0],
+                                      [0.0, 1.0, 0.0, 0.0],
+                                      [0.0, 0.0, 1.0, 0.0]];
+
+pub const NO_TRANSFORM : Transform = Transform { m: IDENTITY,
+                                                 inv: IDENTITY };
+
+fn mul(a: &[[Float ; 4] ; 3], b: &[[Float ; 4] ; 3]) -> [[Float ; 4] ; 3] {
+    let mut r = [[0.0 ; 4] ; 3];
+    for (i, row) in r.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+        row[3] += a[i][3];
+    }
+    r
+}
+
+// The transform of rows `m`, None when it cannot be inverted.
+pub fn matrix(m: [[Float ; 4] ; 3]) -> Option<Transform> {
+    // inverse of the linear part from its cofactors
+    let c = |i: usize, j: usize| {
+        let (i1, i2, j1, j2) = ((i + 1) % 3, (i + 2) % 3,
+                                (j + 1) % 3, (j + 2) % 3);
+        m[i1][j1] * m[i2][j2] - m[i1][j2] * m[i2][j1]
+    };
+    let det = m[0][0] * c(0, 0) + m[0][1] * c(0, 1) + m[0][2] * c(0, 2);
+    if det.abs() < Float::EPSILON {
+        return None;
+    }
+    let mut inv = IDENTITY;
+    for (i, row) in inv.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().take(3).enumerate() {
+            *v = c(j, i) / det;
+        }
+        row[3] = -(0..3).map(|k| row[k] * m[k][3]).sum::<Float>();
+    }
+    Some(Transform { m, inv })
+}
+
+impl Transform {
+    // (transform (matrix m00 m01 m02 m03 m10 ... m23))
+    pub fn to_expr(self) -> Expr {
+        let values = self.m.iter().flatten().map(|v| new_float(*v))
+            .collect();
+        new_form("transform", vec![new_form("matrix", values)])
+    }
+
+    // The transform applying `self`, then `t`.
+    pub fn then(&self, t: &Transform) -> Transform {
+        Transform { m: mul(&t.m, &self.m), inv: mul(&self.inv, &t.inv) }
+    }
+
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        apply(&self.m, p, 1.0)
+    }
+
+    pub fn inv_point(&self, p: &Vec3) -> Vec3 {
+        apply(&self.inv, p, 1.0)
+    }
+
+    pub fn inv_vector(&self, v: &Vec3) -> Vec3 {
+        apply(&self.inv, v, 0.0)
+    }
+
+    // Normals go through the transpose of the inverse.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.inv;
+        Vec3(m[0][0] * n.0 + m[1][0] * n.1 + m[2][0] * n.2,
+             m[0][1] * n.0 + m[1][1] * n.1 + m[2][1] * n.2,
+             m[0][2] * n.0 + m[1][2] * n.1 + m[2][2] * n.2).normalized()
+    }
+}
+
+fn apply(m: &[[Float ; 4] ; 3], v: &Vec3, w: Float) -> Vec3 {
+    Vec3(m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2 + m[0][3] * w,
+         m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2 + m[1][3] * w,
+         m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2 + m[2][3] * w)
+}
+
+pub fn translate(t: Vec3) -> Transform {
+    let mut m = IDENTITY;
+    let mut inv = IDENTITY;
+    for (i, v) in [t.0, t.1, t.2].iter().enumerate() {
+        m[i][3] = *v;
+        inv[i][3] = -*v;
+    }
+    Transform { m, inv }
+}
+
+// None when a factor is zero, flattening the objects.
+pub fn scale(s: Vec3) -> Option<Transform> {
+    let mut m = IDENTITY;
+    let mut inv = IDENTITY;
+    for (i, v) in [s.0, s.1, s.2].iter().enumerate() {
+        if v.abs() < Float::EPSILON {
+            return None;
+        }
+        m[i][i] = *v;
+        inv[i][i] = 1.0 / *v;
+    }
+    Some(Transform { m, inv })
+}
+
+// Rotation of `degrees` around the x (0), y (1) or z (2) axis.
+pub fn rotate(axis: usize, degrees: Float) -> Transform {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut m = IDENTITY;
+    m[a][a] = cos;
+    m[a][b] = -sin;
+    m[b][a] = sin;
+    m[b][b] = cos;
+    // the inverse of a rotation is its transpose
+    let mut inv = m;
+    inv[a][b] = sin;
+    inv[b][a] = -sin;
+    Transform { m, inv }
+}
+
+// An object of a group placed in the scene. The object is shared by all
+// the instances of the group.
+pub struct Instance {
+    object: Arc<dyn Intersect>,
+    transform: Transform
+}
+
+pub fn new_instance(object: Arc<dyn Intersect>, transform: Transform)
+                    -> Box<Instance> {
+    Box::new(Instance { object, transform })
+}
+
+// The ray is moved to the space of the object rather than the object to
+// the space of the ray. The direction is not normalized so that the
+// distance along the ray is the same in both spaces.
+impl Intersect for Instance {
+    fn intersect(&self, orig : &Vec3, dir : &Vec3, time : Float)
+                 -> Option<Float> {
+        self.object.intersect(&self.transform.inv_point(orig),
+                              &self.transform.inv_vector(dir), time)
+    }
+
+    fn get_surface(&self, v : &Vec3, time : Float) -> Vec3 {
+        let p = self.transform.inv_point(v);
+        self.transform.point(&self.object.get_surface(&p, time))
+    }
+
+    fn get_normal(&self, v : &Vec3, time : Float) -> Vec3 {
+        let p = self.transform.inv_point(v);
+        self.transform.normal(&self.object.get_normal(&p, time))
+    }
+
+    fn get_material(&self) -> usize {
+        self.object.get_material()
+    }
+
+    // Written as (instance object (transform ...)), with the object in
+    // place of the name of a group: the scene writer makes the groups.
+    fn to_expr(&self, materials: &[String]) -> Expr {
+        let mut args = vec![self.object.to_expr(materials)];
+        if self.transform != NO_TRANSFORM {
+            args.push(self.transform.to_expr());
+        }
+        new_form("instance", args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4 &&
+            (a.2 - b.2).abs() < 1e-4
+    }
+
+    #[test]
+    fn matrix_inverse() {
+        let t = matrix([[2.0, 1.0, 0.0, 1.0],
+                        [0.0, 1.0, 3.0, -2.0],
+                        [1.0, 0.0, 1.0, 0.5]]).unwrap();
+        let p = Vec3(1.0, 2.0, 3.0);
+        assert!(close(t.point(&p), Vec3(5.0, 9.0, 4.5)));
+        assert!(close(t.inv_point(&t.point(&p)), p));
+        assert!(close(t.point(&t.inv_point(&p)), p));
+        assert_eq!(matrix([[1.0, 2.0, 3.0, 0.0],
+                           [2.0, 4.0, 6.0, 0.0],
+                           [0.0, 0.0, 1.0, 0.0]]), None);
+    }
+
+    #[test]
+    fn then_applies_in_order() {
+        let t = scale(Vec3(2.0, 2.0, 2.0)).unwrap()
+            .then(&rotate(2, 90.0))
+            .then(&translate(Vec3(1.0, 0.0, 0.0)));
+        let p = Vec3(1.0, 0.0, 0.0);
+        assert!(close(t.point(&p), Vec3(1.0, 2.0, 0.0)));
+        assert!(close(t.inv_point(&Vec3(1.0, 2.0, 0.0)), p));
+        assert!(close(t.inv_vector(&Vec3(0.0, 2.0, 0.0)),
+                      Vec3(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn zero_scale_is_rejected() {
+        assert_eq!(scale(Vec3(1.0, 0.0, 2.0)), None);
+        assert!(scale(Vec3(-1.0, 0.5, 2.0)).is_some());
+    }
+}
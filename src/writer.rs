@@ -0,0 +1,195 @@
+use std::io;
+use std::io::prelude::*;
+use crate::vec3::Vec3;
+use crate::expr::{Expr, car, cdr, list_to_vec, new_form,
+                  new_symbol, new_float, new_vec, pretty};
+use crate::raytracer::*;
+use crate::sampler::{DEFAULT_SAMPLER, sampler_kind_name};
+use crate::filter::{DEFAULT_FILTER, filter_kind_name};
+use crate::tile::{DEFAULT_TILES, tile_order_name};
+use crate::tonemap::{DEFAULT_TONEMAP, tone_mapper_name};
+use crate::denoise::denoise_kind_name;
+use crate::post::PostEffect;
+
+// Writes the scene in the canonical form of the scene language: settings
+// differing from the defaults first, then the camera, the light, the
+// materials, the groups and the objects, one form per paragraph.
+// Expressions, loops and includes of the original file are expanded.
+pub fn write_scene(scene: &Scene, out: &mut dyn Write) -> io::Result<()> {
+    for (i, section) in scene_to_exprs(scene).iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        for form in section {
+            writeln!(out, "{}", pretty(form, 0))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn scene_to_exprs(scene: &Scene) -> Vec<Vec<Expr>> {
+    let mut groups = Vec::new();
+    let names = unique_names(&scene.material_names);
+    let objects = scene.objects.iter()
+        .map(|obj| hoist(&obj.to_expr(&names), &mut groups))
+        .collect();
+    let materials = scene.materials.iter().zip(&names)
+        .map(|(m, name)| {
+            new_form("mat", [vec![new_symbol(name)], m.to_expr()].concat())
+        })
+        .collect();
+    let groups = groups.iter().enumerate()
+        .map(|(i, obj)| {
+            new_form("group", vec![new_symbol(&group_name(i)), obj.clone()])
+        })
+        .collect();
+    vec![settings_to_exprs(scene), camera_to_exprs(&scene.camera),
+         vec![new_form("light", vec![new_vec(&scene.lights.dir)])],
+         materials, groups, objects]
+        .into_iter()
+        .filter(|section: &Vec<Expr>| !section.is_empty())
+        .collect()
+}
+
+// The materials are all written before the objects: those shadowed by a
+// later material of the same name are given a name of their own.
+fn unique_names(names: &[String]) -> Vec<String> {
+    let mut unique : Vec<String> = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        let mut name = name.clone();
+        let mut n = 1;
+        while names[i + 1..].contains(&name) || unique.contains(&name) {
+            n += 1;
+            name = format!("{}-{}", names[i], n);
+        }
+        unique.push(name);
+    }
+    unique
+}
+
+fn group_name(i: usize) -> String {
+    format!("group{}", i + 1)
+}
+
+// Instances are written by the objects with their shared object inline,
+// which is moved to a group, the same objects sharing the same group.
+fn hoist(expr: &Expr, groups: &mut Vec<Expr>) -> Expr {
+    if car(expr) != new_symbol("instance") {
+        return expr.clone();
+    }
+    let args = list_to_vec(&cdr(expr));
+    let object = hoist(&args[0], groups);
+    let i = groups.iter().position(|g| *g == object).unwrap_or_else(|| {
+        groups.push(object);
+        groups.len() - 1
+    });
+    new_form("instance", [vec![new_symbol(&group_name(i))],
+                          args[1..].to_vec()].concat())
+}
+
+fn num(n: impl Into<i64>) -> Expr {
+    Expr::Num(n.into())
+}
+
+fn camera_to_exprs(cam: &Camera) -> Vec<Expr> {
+    let mut forms = vec![new_form("camera", vec![num(cam.width),
+                                                 num(cam.height),
+                                                 num(cam.depth)])];
+    if cam.eye != Vec3(0.0, 0.0, 0.0) {
+        forms.push(new_form("eye", vec![new_vec(&cam.eye)]));
+    }
+    if cam.aperture > 0.0 {
+        forms.push(new_form("aperture", vec![new_float(cam.aperture),
+                                             num(cam.blades)]));
+        forms.push(new_form("focus-distance", vec![new_float(cam.focus)]));
+    }
+    if cam.shutter != (0.0, 0.0) {
+        forms.push(new_form("shutter", vec![new_float(cam.shutter.0),
+                                            new_float(cam.shutter.1)]));
+    }
+    forms
+}
+
+fn settings_to_exprs(scene: &Scene) -> Vec<Expr> {
+    let mut forms = Vec::new();
+    if scene.reflections != REFLECTIONS {
+        forms.push(new_form("reflections", vec![num(scene.reflections)]));
+    }
+    let s = scene.sampler;
+    if s != DEFAULT_SAMPLER {
+        forms.push(new_form("sampler", vec![
+            new_symbol(sampler_kind_name(s.kind)),
+            num(s.samples),
+            new_float(s.threshold)]));
+    }
+    let f = scene.filter;
+    if f != DEFAULT_FILTER {
+        forms.push(new_form("filter", vec![new_symbol(filter_kind_name(f.kind)),
+                                           new_float(f.radius)]));
+    }
+    let t = scene.tiles;
+    if t != DEFAULT_TILES {
+        forms.push(new_form("tiles", vec![
+            num(t.size), new_symbol(tile_order_name(t.order))]));
+    }
+    if let Some(p) = scene.progressive {
+        let mut args = vec![num(p.samples)];
+        if p.time.is_some() || p.noise.is_some() {
+            // () stands for no time limit
+            args.push(p.time.map_or(Expr::Nil, new_float));
+        }
+        args.extend(p.noise.map(new_float));
+        forms.push(new_form("progressive", args));
+    }
+    if scene.tone.mapper != DEFAULT_TONEMAP.mapper {
+        forms.push(new_form("tonemap", vec![
+            new_symbol(tone_mapper_name(scene.tone.mapper))]));
+    }
+    if scene.tone.exposure != DEFAULT_TONEMAP.exposure {
+        forms.push(new_form("exposure",
+                            vec![new_float(scene.tone.exposure)]));
+    }
+    if let Some(d) = scene.denoise {
+        forms.push(new_form("denoise", vec![
+            new_symbol(denoise_kind_name(d.kind)), num(d.radius)]));
+    }
+    if !scene.post.is_empty() {
+        forms.push(new_form("post", scene.post.iter()
+                            .map(effect_to_expr)
+                            .collect()));
+    }
+    forms
+}
+
+fn effect_to_expr(effect: &PostEffect) -> Expr {
+    match *effect {
+        PostEffect::Bloom { threshold, radius } =>
+            new_form("bloom", vec![new_float(threshold), new_float(radius)]),
+        PostEffect::Vignette { strength } =>
+            new_form("vignette", vec![new_float(strength)]),
+        PostEffect::ChromaticAberration { amount } =>
+            new_form("chromatic-aberration", vec![new_float(amount)]),
+        PostEffect::Grain { amount } =>
+            new_form("grain", vec![new_float(amount)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::scene::load_scene;
+    use crate::testutil::{temp_file, written};
+
+    #[test]
+    fn written_scene_loads_the_same() {
+        let input = concat!(env!("CARGO_MANIFEST_DIR"), "/test.conf");
+        let scene = load_scene(input, 0).unwrap();
+        let text = written(&scene);
+        let file = temp_file("conf");
+        fs::write(&file.path, &text).unwrap();
+        let reloaded = load_scene(file.name(), 0).unwrap();
+        assert_eq!(reloaded.objects.len(), scene.objects.len());
+        assert_eq!(reloaded.material_names, scene.material_names);
+        assert_eq!(written(&reloaded), text);
+    }
+}